@@ -1,4 +1,7 @@
 use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::moxen::manifest::MoxCategory;
 
 #[derive(Parser, Debug)]
 #[clap(name = "Moxen")]
@@ -9,6 +12,10 @@ pub struct Cli {
     #[clap(short, long)]
     pub directory: Option<String>,
 
+    /// Registry to use, either a name from the Moxen config or a URL
+    #[clap(long, env = "MOXEN_REGISTRY", global = true)]
+    pub registry: Option<String>,
+
     #[clap(subcommand)]
     pub commands: Commands,
 }
@@ -27,20 +34,126 @@ pub enum Commands {
 
     /// Add a package as a dependency of this addon/library
     Add {
-        /// Name of the package to add
+        /// Name of the package to add (optionally `name@version-requirement`).
+        /// Installs every dependency in Moxen.toml when omitted
         #[clap(value_delimiter = ' ')]
         names: Vec<String>,
+
+        /// Fail instead of changing Moxen.lock
+        #[clap(long)]
+        locked: bool,
+
+        /// Leave Moxen.toml and libs/ untouched unless every package installs
+        #[clap(long)]
+        all_or_nothing: bool,
+
+        /// Refuse packages without a valid publisher signature
+        #[clap(long)]
+        require_signatures: bool,
+    },
+
+    /// Update dependencies to the newest versions allowed by Moxen.toml
+    Update {
+        /// Name of the package to update. Updates everything when omitted
+        #[clap(value_delimiter = ' ')]
+        names: Vec<String>,
+
+        /// Refuse packages without a valid publisher signature
+        #[clap(long)]
+        require_signatures: bool,
+    },
+
+    /// Remove packages from the dependencies of this addon/library
+    Remove {
+        /// Name of the package to remove
+        #[clap(value_delimiter = ' ', required = true)]
+        names: Vec<String>,
+    },
+
+    /// Display the dependency graph of this addon/library
+    Tree {
+        /// Show which packages pull in this package instead
+        #[clap(long)]
+        invert: Option<String>,
+
+        /// Output format of the graph
+        #[clap(long, value_enum, default_value_t = TreeFormat::Text)]
+        format: TreeFormat,
     },
 
-    /// Display information about a Moxen project
-    Info,
+    /// Search the registry for packages
+    Search {
+        /// Words to look for in package names and descriptions
+        terms: Vec<String>,
+
+        /// Only show packages in this category
+        #[clap(long, value_enum)]
+        category: Option<MoxCategory>,
+
+        /// Only show packages for this WoW version (e.g. 11.0)
+        #[clap(long)]
+        wow_version: Option<String>,
+
+        /// Only show packages by this author
+        #[clap(long)]
+        author: Option<String>,
+
+        /// Print the results as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Display information about a Moxen project, or a package on the registry
+    Info {
+        /// Registry package to look up, optionally as name@version
+        package: Option<String>,
+    },
 
     /// Package a Moxen project ready for publishing
     Package,
 
+    /// Check the TOC files for missing, unlisted and duplicate files
+    Check,
+
+    /// Generate the TOC file from Moxen.toml
+    Toc {
+        /// Replace a TOC which was not generated by Moxen
+        #[clap(long)]
+        force: bool,
+    },
+
     /// Publish a Moxen project to the registry
     Publish,
 
+    /// Retract a published version so new installs no longer pick it
+    Yank {
+        /// Version to yank, as name@version
+        package: String,
+
+        /// Restore a previously yanked version
+        #[clap(long)]
+        undo: bool,
+    },
+
+    /// Manage who can publish a package
+    #[clap(group(clap::ArgGroup::new("action").required(true).args(["add", "remove", "list"])))]
+    Owner {
+        /// Package to manage, defaults to this addon/library
+        package: Option<String>,
+
+        /// Allow a registered user to publish the package
+        #[clap(long)]
+        add: Option<String>,
+
+        /// Stop a user from publishing the package
+        #[clap(long)]
+        remove: Option<String>,
+
+        /// List the owners of the package
+        #[clap(long)]
+        list: bool,
+    },
+
     /// Register to the Moxen registry
     Register {
         /// Username for the registry
@@ -63,7 +176,8 @@ pub enum Commands {
     Clean,
 }
 
-#[derive(clap::ValueEnum, Debug, Clone)]
+#[derive(clap::ValueEnum, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DocumentationType {
     Live,
     Beta,
@@ -71,6 +185,33 @@ pub enum DocumentationType {
     Vanilla,
 }
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum TreeFormat {
+    Text,
+    Dot,
+}
+
+impl Commands {
+    /// Whether the command works on a Moxen project and so needs its Moxen.toml
+    pub fn needs_manifest(&self) -> bool {
+        !matches!(
+            self,
+            Self::New { .. }
+                | Self::Register { .. }
+                | Self::Recover { .. }
+                | Self::Clean
+                | Self::Search { .. }
+                | Self::Moxify
+                | Self::Info { package: Some(_) }
+                | Self::Yank { .. }
+                | Self::Owner {
+                    package: Some(_),
+                    ..
+                }
+        )
+    }
+}
+
 impl DocumentationType {
     pub fn repo(&self) -> String {
         "https://github.com/Gethe/wow-ui-source".to_string()
@@ -84,4 +225,13 @@ impl DocumentationType {
             Self::Vanilla => "classic_era".to_string(),
         }
     }
+
+    /// Suffix of the TOC file the game client loads in preference to the plain one
+    pub fn toc_suffix(&self) -> &'static str {
+        match self {
+            Self::Live | Self::Beta => "Mainline",
+            Self::Classic => "Cata",
+            Self::Vanilla => "Vanilla",
+        }
+    }
 }
@@ -1,11 +1,14 @@
 use anyhow::Result;
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use sha1::{Digest, Sha1};
-use std::path::{Path, PathBuf};
-use tar::Archive;
+use sha2::Sha256;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
 
 const DEBUG: bool = false;
-const INVALID_FILETYPE: [&'static str; 6] = ["exe", "c", "cpp", "rs", "js", "cs"];
+const INVALID_FILETYPE: [&str; 6] = ["exe", "c", "cpp", "rs", "js", "cs"];
+const MAX_UNPACKED_SIZE: u64 = 512 * 1024 * 1024;
+const MAX_UNPACKED_FILES: usize = 20_000;
 
 #[derive(Debug)]
 pub enum MoxenError {
@@ -15,6 +18,14 @@ pub enum MoxenError {
     ProjectAlreadyExists,
     ProjectNotFound(String),
     ChecksumFailure((String, String)),
+    UnsafePackageEntry((String, String)),
+    SignatureFailure((String, String)),
+    NoMatchingVersion((String, String)),
+    LockfileOutdated(String),
+    DependencyCycle(String),
+    DependencyConflict(String),
+    InstallFailure(usize),
+    TocCheckFailure(usize),
     ConfigError(String),
     LoadError(String),
     InvalidUsername(String),
@@ -36,6 +47,25 @@ impl std::fmt::Display for MoxenError {
             Self::ChecksumFailure((chk1, chk2)) => {
                 writeln!(f, "checksum failure: {chk1} doesn't match expected {chk2}")
             }
+            Self::UnsafePackageEntry((entry, reason)) => {
+                writeln!(f, "refusing to unpack package entry `{entry}`: {reason}")
+            }
+            Self::SignatureFailure((pkg, reason)) => {
+                writeln!(f, "signature check failed for {pkg}: {reason}")
+            }
+            Self::NoMatchingVersion((pkg, req)) => {
+                writeln!(f, "no version of {pkg} matches the requirement {req}")
+            }
+            Self::LockfileOutdated(reason) => {
+                writeln!(
+                    f,
+                    "Moxen.lock needs updating but --locked was passed: {reason}"
+                )
+            }
+            Self::DependencyCycle(cycle) => writeln!(f, "dependency cycle detected: {cycle}"),
+            Self::DependencyConflict(reason) => writeln!(f, "version conflict: {reason}"),
+            Self::InstallFailure(count) => writeln!(f, "{count} package(s) failed to install"),
+            Self::TocCheckFailure(count) => writeln!(f, "{count} error(s) found in the TOC files"),
             Self::LoadError(err) => writeln!(f, "loading error: {err}"),
             Self::ConfigError(err) => {
                 writeln!(f, "config error: {err}")
@@ -80,7 +110,7 @@ pub fn gather_files(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-fn invalid_filetypes(file: &PathBuf) -> Result<()> {
+fn invalid_filetypes(file: &Path) -> Result<()> {
     match file.extension() {
         Some(ext) => {
             let ext = ext.to_str().unwrap();
@@ -88,7 +118,7 @@ fn invalid_filetypes(file: &PathBuf) -> Result<()> {
                 anyhow::bail!(MoxenError::InvalidFileExtension(ext.to_owned()));
             }
 
-            return Ok(());
+            Ok(())
         }
         None => Ok(()),
     }
@@ -103,7 +133,7 @@ fn iterdir(dir: impl AsRef<Path>, collector: &mut Vec<PathBuf>) -> Result<()> {
         } else {
             match invalid_filetypes(&path) {
                 Ok(_) => collector.push(path),
-                Err(e) => return Err(e.into()),
+                Err(e) => return Err(e),
             }
         }
     }
@@ -111,29 +141,228 @@ fn iterdir(dir: impl AsRef<Path>, collector: &mut Vec<PathBuf>) -> Result<()> {
     Ok(())
 }
 
-pub fn tarball(src: &PathBuf, dst: &PathBuf) -> Result<()> {
-    let output = std::fs::File::create(&dst)?;
+/// Packs `src` into a gzipped tarball at `dst`.
+///
+/// Entries are sorted and stamped with `mtime`, root ownership and fixed modes so that
+/// the same sources always give a byte-identical archive.
+pub fn tarball(src: &Path, dst: &Path, mtime: u64) -> Result<()> {
+    let mut entries = vec![];
+    collect_entries(src, Path::new(""), &mut entries)?;
+    pack_entries(src, entries, dst, mtime)
+}
+
+/// Packs the `entries` of `src`, in whatever order the directory listing gave them
+fn pack_entries(src: &Path, mut entries: Vec<PathBuf>, dst: &Path, mtime: u64) -> Result<()> {
+    entries.sort();
+
+    let output = std::fs::File::create(dst)?;
     let enc = GzEncoder::new(output, Compression::default());
     let mut tar = tar::Builder::new(enc);
-    tar.append_dir_all(".", src)?;
+    for entry in entries.iter() {
+        let path = src.join(entry);
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("")?;
+        header.set_groupname("")?;
+
+        if path.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, entry, std::io::empty())?;
+        } else {
+            let file = std::fs::File::open(&path)?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(file.metadata()?.len());
+            tar.append_data(&mut header, entry, file)?;
+        }
+    }
+    tar.into_inner()?.finish()?;
+
     Ok(())
 }
 
-pub fn untarball(path: &PathBuf, data: Vec<u8>) -> Result<()> {
-    let tar = GzDecoder::new(data.as_slice());
-    let mut archive = Archive::new(tar);
-    archive.unpack(&path)?;
+/// Relative paths of every directory and file below `dir`
+fn collect_entries(root: &Path, dir: &Path, collector: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let relative = dir.join(entry.file_name());
+        collector.push(relative.clone());
+        if entry.file_type()?.is_dir() {
+            collect_entries(root, &relative, collector)?;
+        }
+    }
+
     Ok(())
 }
 
-pub fn validate_package_checksum(data: &Vec<u8>, checksum: &str) -> Result<(), MoxenError> {
-    let mut hasher = Sha1::new();
-    hasher.update(data);
-    let check = hex::encode(hasher.finalize());
-    if check == checksum {
+/// `path` as a relative path which cannot leave the directory it is joined onto, or
+/// `None` if it is empty, absolute or has any `.` or `..` component
+pub fn contained_path(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let contained = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+    contained.then(|| path.to_path_buf())
+}
+
+/// Unpacks a registry package into `path`.
+///
+/// Every entry is checked in a first pass over the package before anything is written:
+/// only plain files and directories that stay inside `path` are allowed, and the package
+/// as a whole is capped in size and number of entries. `open` is called once per pass.
+pub fn untarball<R: std::io::Read>(path: &Path, open: impl Fn() -> Result<R>) -> Result<()> {
+    let mut limits = UnpackLimits::default();
+    for entry in Archive::new(GzDecoder::new(open()?)).entries()? {
+        limits.check(&entry?)?;
+    }
+
+    let mut limits = UnpackLimits::default();
+    for entry in Archive::new(GzDecoder::new(open()?)).entries()? {
+        let mut entry = entry?;
+        let Some(relative) = limits.check(&entry)? else {
+            continue;
+        };
+
+        let target = path.join(&relative);
+        if entry.header().entry_type() == EntryType::Directory {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&target)?;
+    }
+
+    Ok(())
+}
+
+/// Running totals of a package being unpacked
+#[derive(Default)]
+struct UnpackLimits {
+    total_size: u64,
+    file_count: usize,
+}
+
+impl UnpackLimits {
+    /// Relative path an entry unpacks to, `None` for entries which are skipped
+    fn check<R: std::io::Read>(&mut self, entry: &tar::Entry<R>) -> Result<Option<PathBuf>> {
+        let entry_path = entry.path()?.into_owned();
+        let unsafe_entry = |reason: &str| {
+            MoxenError::UnsafePackageEntry((entry_path.display().to_string(), reason.to_string()))
+        };
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Directory => {}
+            EntryType::XGlobalHeader => return Ok(None),
+            EntryType::Symlink | EntryType::Link => {
+                anyhow::bail!(unsafe_entry("links are not allowed"))
+            }
+            _ => anyhow::bail!(unsafe_entry("only files and directories are allowed")),
+        }
+
+        let mut relative = PathBuf::new();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                _ => anyhow::bail!(unsafe_entry("path escapes the package directory")),
+            }
+        }
+        if relative.as_os_str().is_empty() {
+            return Ok(None);
+        }
+
+        self.file_count += 1;
+        if self.file_count > MAX_UNPACKED_FILES {
+            anyhow::bail!(unsafe_entry(&format!(
+                "package has more than {MAX_UNPACKED_FILES} entries"
+            )));
+        }
+
+        self.total_size += entry.header().size()?;
+        if self.total_size > MAX_UNPACKED_SIZE {
+            anyhow::bail!(unsafe_entry(&format!(
+                "package unpacks to more than {} MiB",
+                MAX_UNPACKED_SIZE / 1024 / 1024
+            )));
+        }
+
+        Ok(Some(relative))
+    }
+}
+
+/// Hashes package data with every supported checksum algorithm in one pass
+#[derive(Default)]
+pub struct PackageHasher {
+    sha1: Sha1,
+    sha256: Sha256,
+}
+
+/// Checksums of a package: bare SHA-1 hex (legacy) and SHA-256 in `algo:hex` form
+#[derive(Debug, Clone)]
+pub struct PackageChecksums {
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl PackageHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    pub fn finalize(self) -> PackageChecksums {
+        PackageChecksums {
+            sha1: hex::encode(self.sha1.finalize()),
+            sha256: format!("sha256:{}", hex::encode(self.sha256.finalize())),
+        }
+    }
+}
+
+impl std::io::Write for PackageHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
-    } else {
-        Err(MoxenError::ChecksumFailure((check, checksum.to_string())))
+    }
+}
+
+impl PackageChecksums {
+    pub fn of_file(path: &Path) -> Result<Self> {
+        let mut hasher = PackageHasher::default();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(hasher.finalize())
+    }
+
+    /// Checks against an `algo:hex` checksum. A bare hex string is a legacy SHA-1 checksum.
+    pub fn verify(&self, checksum: &str) -> Result<(), MoxenError> {
+        let check = match checksum.split_once(':') {
+            Some(("sha256", _)) => self.sha256.clone(),
+            Some(("sha1", _)) => format!("sha1:{}", self.sha1),
+            Some((algo, _)) => {
+                return Err(MoxenError::GeneralError(format!(
+                    "unsupported checksum algorithm `{algo}`"
+                )))
+            }
+            None => self.sha1.clone(),
+        };
+
+        if check == checksum {
+            Ok(())
+        } else {
+            Err(MoxenError::ChecksumFailure((check, checksum.to_string())))
+        }
     }
 }
 
@@ -150,3 +379,147 @@ pub fn copy_directory(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A gzipped tarball of `(path, contents)` entries, written without the path checks
+    /// `tar::Builder` does so that unsafe entries can be made
+    fn package(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            let name = &mut header.as_old_mut().name;
+            name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append(&header, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn unpack(data: &[u8]) -> (tempfile::TempDir, Result<()>) {
+        let dir = tempfile::tempdir().unwrap();
+        let result = untarball(dir.path(), || Ok(data));
+        (dir, result)
+    }
+
+    #[test]
+    fn tarballs_are_reproducible() {
+        let files = [
+            ("Core.lua", "print(1)"),
+            ("UI/Frame.xml", "<Ui/>"),
+            ("UI/Frame.lua", ""),
+            ("Locales/enUS.lua", "L = {}"),
+        ];
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        // Create the second tree in the opposite order, with other mtimes and permissions
+        for (dir, order, mtime) in [
+            (&first, files.to_vec(), 1_000_000),
+            (&second, files.iter().rev().cloned().collect(), 2_000_000),
+        ] {
+            for (path, contents) in order {
+                let path = dir.path().join("src").join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, contents).unwrap();
+                let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                let file = std::fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(modified).unwrap();
+            }
+        }
+        let mut permissions = std::fs::metadata(second.path().join("src/Core.lua"))
+            .unwrap()
+            .permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(second.path().join("src/Core.lua"), permissions).unwrap();
+
+        tarball(&first.path().join("src"), &first.path().join("pkg.mox"), 42).unwrap();
+        // The file system lists both trees alike, so list the second one backwards
+        let src = second.path().join("src");
+        let mut entries = vec![];
+        collect_entries(&src, Path::new(""), &mut entries).unwrap();
+        entries.reverse();
+        pack_entries(&src, entries, &second.path().join("pkg.mox"), 42).unwrap();
+        assert_eq!(
+            std::fs::read(first.path().join("pkg.mox")).unwrap(),
+            std::fs::read(second.path().join("pkg.mox")).unwrap()
+        );
+    }
+
+    #[test]
+    fn unpacks_files() {
+        let (dir, result) = unpack(&package(&[
+            ("Core.lua", b"print(1)"),
+            ("UI/Frame.xml", b""),
+        ]));
+        result.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("Core.lua")).unwrap(),
+            "print(1)"
+        );
+        assert!(dir.path().join("UI/Frame.xml").is_file());
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_package_before_writing() {
+        for path in ["../evil.lua", "/tmp/evil.lua", "UI/../../evil.lua"] {
+            let (dir, result) = unpack(&package(&[("Core.lua", b""), (path, b"")]));
+            let err = result.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<MoxenError>(),
+                Some(MoxenError::UnsafePackageEntry(_))
+            ));
+            assert!(!dir.path().join("Core.lua").exists(), "{path}");
+        }
+    }
+
+    #[test]
+    fn refuses_links() {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "libs", "/etc").unwrap();
+        let data = builder.into_inner().unwrap().finish().unwrap();
+
+        let (_dir, result) = unpack(&data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn contained_paths_stay_relative() {
+        assert_eq!(
+            contained_path("libs/LibStub"),
+            Some(PathBuf::from("libs/LibStub"))
+        );
+        assert_eq!(contained_path(""), None);
+        assert_eq!(contained_path("/libs"), None);
+        assert_eq!(contained_path("./libs"), None);
+        assert_eq!(contained_path("libs/../.."), None);
+    }
+
+    #[test]
+    fn verifies_checksums() {
+        let mut hasher = PackageHasher::default();
+        hasher.update(b"package");
+        let checksums = hasher.finalize();
+
+        checksums.verify(&checksums.sha1).unwrap();
+        checksums.verify(&checksums.sha256).unwrap();
+        checksums
+            .verify(&format!("sha1:{}", checksums.sha1))
+            .unwrap();
+        assert!(matches!(
+            checksums.verify("sha256:0000"),
+            Err(MoxenError::ChecksumFailure(_))
+        ));
+        assert!(matches!(
+            checksums.verify("md5:0000"),
+            Err(MoxenError::GeneralError(_))
+        ));
+    }
+}
@@ -6,20 +6,66 @@ mod common;
 mod moxen;
 
 use cli::*;
-use moxen::Manager;
+use moxen::{api::SearchQuery, Manager};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let mut pkg_mgr = Manager::new(cli.directory)?;
+    let mut pkg_mgr = Manager::new(cli.directory, cli.registry, cli.commands.needs_manifest())?;
 
     match cli.commands {
         Commands::New { name, docs } => pkg_mgr.bootstrap(name, docs)?,
-        Commands::Add { names } => pkg_mgr.download_dependencies(names).await?,
-        Commands::Info => pkg_mgr.info(),
+        Commands::Add {
+            names,
+            locked,
+            all_or_nothing,
+            require_signatures,
+        } => {
+            pkg_mgr
+                .download_dependencies(names, locked, all_or_nothing, require_signatures)
+                .await?
+        }
+        Commands::Update {
+            names,
+            require_signatures,
+        } => {
+            pkg_mgr
+                .update_dependencies(names, require_signatures)
+                .await?
+        }
+        Commands::Remove { names } => pkg_mgr.remove_dependencies(names)?,
+        Commands::Tree { invert, format } => pkg_mgr.tree(invert, format)?,
+        Commands::Search {
+            terms,
+            category,
+            wow_version,
+            author,
+            json,
+        } => {
+            let query = SearchQuery {
+                terms,
+                category,
+                wow_version,
+                author,
+            };
+            pkg_mgr.search(query, json).await?
+        }
+        Commands::Info { package } => match package {
+            Some(package) => pkg_mgr.remote_info(&package).await?,
+            None => pkg_mgr.info()?,
+        },
         Commands::Package => {
             pkg_mgr.package()?;
         }
+        Commands::Check => pkg_mgr.check()?,
+        Commands::Toc { force } => pkg_mgr.toc(force)?,
+        Commands::Yank { package, undo } => pkg_mgr.yank(&package, undo).await?,
+        Commands::Owner {
+            package,
+            add,
+            remove,
+            ..
+        } => pkg_mgr.owners(package, add, remove).await?,
         Commands::Register { name } => pkg_mgr.register(name).await?,
         Commands::Recover {
             name,
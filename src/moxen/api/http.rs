@@ -0,0 +1,581 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use reqwest::Body;
+use reqwest::Certificate;
+use reqwest::Client;
+use reqwest::{NoProxy, Proxy, RequestBuilder, Response, StatusCode};
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::response::{
+    PackageManifestResponse, PackageOwnersResponse, PackageSignatureResponse,
+    PackageVersionsResponse, PublicKeyResponse, SearchResponse, UserRecoveryResponse,
+    UserRegisterResponse,
+};
+use super::transfer::{DownloadedPackage, PackageWriter};
+use super::{PackageSignature, Registry, RegistryBackend, SearchQuery};
+use crate::{common::MoxenError, moxen::manifest::NormalizedManifest};
+
+/// The hosted Moxen registry API (or a self-hosted copy of it)
+pub struct HttpRegistry {
+    url: String,
+    client: Client,
+    retries: u32,
+}
+
+impl HttpRegistry {
+    pub fn new(registry: &Registry) -> Result<Self> {
+        Ok(Self {
+            url: registry.url.clone(),
+            client: generate_request_client(registry)?,
+            retries: registry.network.retries,
+        })
+    }
+
+    /// Sends a request which is safe to repeat, retrying dropped connections, timeouts
+    /// and server errors with exponential backoff
+    async fn send_idempotent(&self, request: RequestBuilder) -> Result<Response> {
+        self.send_with_retries(request, &mut 0).await
+    }
+
+    /// [`Self::send_idempotent`], counting retries in `attempt` so that callers which
+    /// retry on their own share one budget of `retries`
+    async fn send_with_retries(
+        &self,
+        request: RequestBuilder,
+        attempt: &mut u32,
+    ) -> Result<Response> {
+        loop {
+            let Some(req) = request.try_clone() else {
+                return Ok(request.send().await?);
+            };
+
+            let reason = match req.send().await {
+                Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+                Ok(response) if *attempt >= self.retries => return Ok(response),
+                Err(err) if !is_transient_error(&err) || *attempt >= self.retries => {
+                    return Err(err.into())
+                }
+                Ok(response) => response.status().to_string(),
+                Err(err) => err.to_string(),
+            };
+
+            *attempt += 1;
+            backoff(*attempt, &reason).await;
+        }
+    }
+
+    /// Streams a package tarball to disk, starting over when the connection drops midway
+    async fn download(&self, url: &str, label: &str) -> Result<DownloadedPackage> {
+        let mut attempt = 0;
+        loop {
+            let mut response = self
+                .send_with_retries(self.client.get(url), &mut attempt)
+                .await?;
+            match response.status() {
+                StatusCode::OK => {}
+                StatusCode::NOT_FOUND => {
+                    return Err(MoxenError::ProjectNotFound(label.to_string()).into())
+                }
+                _ => {
+                    let text = response.text().await?;
+                    return Err(MoxenError::ApiError(text).into());
+                }
+            }
+
+            let mut writer = PackageWriter::new()?.with_progress(label, response.content_length());
+            let streamed = loop {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => writer.write_all(&chunk)?,
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+
+            match streamed {
+                Ok(()) => return writer.finish(),
+                Err(err) if attempt >= self.retries => {
+                    writer.discard();
+                    return Err(err.into());
+                }
+                Err(err) => {
+                    writer.discard();
+                    attempt += 1;
+                    backoff(attempt, &err.to_string()).await;
+                }
+            }
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Only failures to reach the registry are retried, a request it may have acted on is not
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect()
+}
+
+/// Waits 0.5s, 1s, 2s, ... before retry number `attempt`
+async fn backoff(attempt: u32, reason: &str) {
+    let delay = Duration::from_millis(500 << (attempt - 1).min(6));
+    eprintln!(
+        "Request failed ({reason}), retrying in {:.1}s...",
+        delay.as_secs_f32()
+    );
+    tokio::time::sleep(delay).await;
+}
+
+fn generate_request_client(registry: &Registry) -> Result<Client> {
+    let network = &registry.network;
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .connect_timeout(Duration::from_secs(network.connect_timeout))
+        .read_timeout(Duration::from_secs(network.read_timeout));
+
+    // An explicit proxy replaces the one reqwest picks up from the environment
+    if let Some(proxy) = &network.proxy {
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| MoxenError::ConfigError(format!("invalid proxy `{proxy}` - {e}")))?;
+        let no_proxy = match &network.no_proxy {
+            Some(hosts) => NoProxy::from_string(hosts),
+            None => NoProxy::from_env(),
+        };
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+
+    if let Some(ca_bundle) = &registry.ca_bundle {
+        let pem = std::fs::read(ca_bundle).map_err(|e| {
+            MoxenError::ConfigError(format!(
+                "unable to read CA bundle {} - {e}",
+                ca_bundle.display()
+            ))
+        })?;
+        for cert in Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if registry.accept_invalid_certs {
+        warn_insecure(registry);
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    let client = builder.build()?;
+
+    Ok(client)
+}
+
+/// Warns (once per registry) that certificate verification is turned off
+fn warn_insecure(registry: &Registry) {
+    static WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if warned.insert(registry.name.clone()) {
+        eprintln!(
+            "WARNING: TLS certificate verification is disabled for the `{}` registry ({}). \
+             Your API key and packages can be intercepted!",
+            registry.name, registry.url
+        );
+    }
+}
+
+/// Builds the multipart upload for a package, streaming the tarball from disk
+async fn create_request_form(
+    manifest: &NormalizedManifest,
+    pkg: &Path,
+    signature: &PackageSignature,
+) -> Result<Form> {
+    let file = tokio::fs::File::open(pkg).await?;
+    let size = file.metadata().await?.len();
+    let file_name = pkg
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("{}.mox", manifest.name));
+    let body = Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+    let package = Part::stream_with_length(body, size)
+        .file_name(file_name)
+        .mime_str("application/gzip")?;
+
+    Ok(Form::new()
+        .text("manifest", toml::to_string(manifest)?)
+        .text("signature", signature.signature.clone())
+        .part("package", package))
+}
+
+async fn send_owner_change(
+    request: reqwest::RequestBuilder,
+    name: &str,
+    owner: &str,
+    api_key: &str,
+    username: &str,
+) -> Result<()> {
+    let mut body = HashMap::new();
+    body.insert("user", owner);
+
+    let response = request
+        .json(&body)
+        .header("x-api-key", api_key)
+        .header("x-authorize-user", username)
+        .send()
+        .await?;
+
+    let status = response.status();
+    match status {
+        StatusCode::OK => Ok(()),
+        StatusCode::NOT_FOUND => Err(MoxenError::ProjectNotFound(name.to_string()).into()),
+        StatusCode::UNAUTHORIZED => Err(MoxenError::ApiError("invalid api key".to_string()).into()),
+        StatusCode::FORBIDDEN => {
+            Err(MoxenError::AuthError(format!("{username} is not an owner of {name}")).into())
+        }
+        _ => {
+            let text = response.text().await?;
+            Err(MoxenError::ApiError(text).into())
+        }
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for HttpRegistry {
+    async fn fetch_mox(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<(String, DownloadedPackage)> {
+        // Pin the version from the manifest so the tarball can't come from a newer publish
+        let manifest = self.fetch_manifest(name, version).await?;
+        let version = toml::from_str::<NormalizedManifest>(&manifest)?.version;
+        let (url, label) = match &version {
+            Some(version) => (
+                format!("{}/api/v1/mox/{name}/{version}/download", self.url),
+                format!("{name} v{version}"),
+            ),
+            None => (
+                format!("{}/api/v1/mox/{name}/download", self.url),
+                name.to_string(),
+            ),
+        };
+
+        let package = self.download(&url, &label).await?;
+
+        Ok((manifest, package))
+    }
+
+    async fn fetch_manifest(&self, name: &str, version: Option<&str>) -> Result<String> {
+        let url = match version {
+            Some(version) => format!("{}/api/v1/mox/{name}/{version}/manifest", self.url),
+            None => format!("{}/api/v1/mox/{name}/manifest", self.url),
+        };
+
+        let response = self.send_idempotent(self.client.get(url)).await?;
+        let status = response.status();
+        let response = response.json::<PackageManifestResponse>().await?;
+        match status {
+            StatusCode::OK => Ok(response.manifest),
+            StatusCode::NOT_FOUND => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ProjectNotFound(error_message).into())
+            }
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn fetch_versions(&self, name: &str) -> Result<Vec<String>> {
+        let url = format!("{}/api/v1/mox/{name}/versions", self.url);
+
+        let response = self.send_idempotent(self.client.get(url)).await?;
+        let status = response.status();
+        let response = response.json::<PackageVersionsResponse>().await?;
+        match status {
+            StatusCode::OK => Ok(response.versions),
+            StatusCode::NOT_FOUND => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ProjectNotFound(error_message).into())
+            }
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn fetch_signature(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Option<PackageSignature>> {
+        let url = match version {
+            Some(version) => format!("{}/api/v1/mox/{name}/{version}/signature", self.url),
+            None => format!("{}/api/v1/mox/{name}/signature", self.url),
+        };
+
+        let response = self.send_idempotent(self.client.get(url)).await?;
+        let status = response.status();
+        let response = response.json::<PackageSignatureResponse>().await?;
+        match status {
+            StatusCode::OK => match (response.publisher, response.signature) {
+                (Some(publisher), Some(signature)) => Ok(Some(PackageSignature {
+                    publisher,
+                    signature,
+                })),
+                _ => Ok(None),
+            },
+            // Packages published before signing was supported
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn fetch_public_key(&self, username: &str) -> Result<String> {
+        let url = format!("{}/api/v1/users/{username}/key", self.url);
+
+        let response = self.send_idempotent(self.client.get(url)).await?;
+        let status = response.status();
+        let response = response.json::<PublicKeyResponse>().await?;
+        match status {
+            StatusCode::OK => Ok(response.key),
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<NormalizedManifest>> {
+        let url = format!("{}/api/v1/mox/search", self.url);
+        let mut params = vec![("q", query.terms.join(" "))];
+        if let Some(category) = query.category {
+            // Same kebab-case spelling the manifests use
+            let category = toml::Value::try_from(category)?;
+            params.push(("category", category.as_str().unwrap_or_default().to_owned()));
+        }
+        if let Some(wow_version) = &query.wow_version {
+            params.push(("wow_version", wow_version.clone()));
+        }
+        if let Some(author) = &query.author {
+            params.push(("author", author.clone()));
+        }
+
+        let response = self
+            .send_idempotent(self.client.get(url).query(&params))
+            .await?;
+        let status = response.status();
+        let response = response.json::<SearchResponse>().await?;
+        match status {
+            StatusCode::OK => Ok(response.results),
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn publish_mox_package(
+        &self,
+        manifest: &NormalizedManifest,
+        package: &Path,
+        signature: &PackageSignature,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        let form = create_request_form(manifest, package, signature).await?;
+        let url = format!("{}/api/v1/mox/new", self.url);
+        let response = self
+            .client
+            .post(url)
+            .multipart(form)
+            .header("x-api-key", api_key)
+            .header("x-authorize-user", username)
+            .send()
+            .await?;
+
+        let status = response.status();
+        match status {
+            StatusCode::CREATED => Ok(()),
+            StatusCode::CONFLICT => Err(MoxenError::ProjectAlreadyExists.into()),
+            StatusCode::UNAUTHORIZED => {
+                Err(MoxenError::ApiError("invalid api key".to_string()).into())
+            }
+            _ => {
+                let text = response.text().await?;
+                Err(MoxenError::ApiError(text).into())
+            }
+        }
+    }
+
+    async fn yank(
+        &self,
+        name: &str,
+        version: &str,
+        yanked: bool,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        let action = if yanked { "yank" } else { "unyank" };
+        let url = format!("{}/api/v1/mox/{name}/{version}/{action}", self.url);
+        let response = self
+            .client
+            .post(url)
+            .header("x-api-key", api_key)
+            .header("x-authorize-user", username)
+            .send()
+            .await?;
+
+        let status = response.status();
+        match status {
+            StatusCode::OK => Ok(()),
+            StatusCode::NOT_FOUND => {
+                Err(MoxenError::ProjectNotFound(format!("{name} {version}")).into())
+            }
+            StatusCode::UNAUTHORIZED => {
+                Err(MoxenError::ApiError("invalid api key".to_string()).into())
+            }
+            _ => {
+                let text = response.text().await?;
+                Err(MoxenError::ApiError(text).into())
+            }
+        }
+    }
+
+    async fn owners(&self, name: &str) -> Result<Vec<String>> {
+        let url = format!("{}/api/v1/mox/{name}/owners", self.url);
+
+        let response = self.send_idempotent(self.client.get(url)).await?;
+        let status = response.status();
+        let response = response.json::<PackageOwnersResponse>().await?;
+        match status {
+            StatusCode::OK => Ok(response.owners),
+            StatusCode::NOT_FOUND => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ProjectNotFound(error_message).into())
+            }
+            _ => {
+                let error_message = response.error.unwrap();
+                Err(MoxenError::ApiError(error_message).into())
+            }
+        }
+    }
+
+    async fn add_owner(
+        &self,
+        name: &str,
+        owner: &str,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        let url = format!("{}/api/v1/mox/{name}/owners", self.url);
+        let request = self.client.post(url);
+        send_owner_change(request, name, owner, api_key, username).await
+    }
+
+    async fn remove_owner(
+        &self,
+        name: &str,
+        owner: &str,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        let url = format!("{}/api/v1/mox/{name}/owners", self.url);
+        let request = self.client.delete(url);
+        send_owner_change(request, name, owner, api_key, username).await
+    }
+
+    async fn generate_challenge(&self, name: &str, pub_key: &str) -> Result<String> {
+        let url = format!("{}/api/v1/auth/challenge", self.url);
+        let mut body = HashMap::new();
+        body.insert("name", name);
+        body.insert("key", pub_key);
+
+        let response = self
+            .send_idempotent(self.client.post(url).json(&body))
+            .await?;
+        let status = response.status();
+        let text = response.text().await?;
+        match status {
+            StatusCode::OK => Ok(text),
+            _ => Err(MoxenError::ApiError(text).into()),
+        }
+    }
+
+    async fn signup(&self, original: String, challenge: String) -> Result<(String, Vec<String>)> {
+        let url = format!("{}/api/v1/auth/register", self.url);
+        let mut body = HashMap::new();
+        body.insert("original", original);
+        body.insert("challenge", challenge);
+
+        let response = self.client.post(url).json(&body).send().await?;
+        let status = response.status();
+        let data = response.json::<UserRegisterResponse>().await?;
+
+        match status {
+            StatusCode::CREATED => Ok((data.api_key, data.recovery_codes)),
+            _ => {
+                let error = data.error.unwrap();
+                Err(MoxenError::ApiError(error).into())
+            }
+        }
+    }
+
+    async fn recover(&self, challenge: String, signed: String, code: String) -> Result<String> {
+        let url = format!("{}/api/v1/auth/recovery", self.url);
+        let mut body = HashMap::new();
+        body.insert("challenge", challenge);
+        body.insert("signed", signed);
+        body.insert("code", code);
+
+        let response = self.client.post(url).json(&body).send().await?;
+        let status = response.status();
+        let data = response.json::<UserRecoveryResponse>().await?;
+
+        match status {
+            StatusCode::OK => Ok(data.api_key),
+            StatusCode::UNAUTHORIZED => {
+                let error_msg = data.error.unwrap();
+                Err(MoxenError::AuthError(error_msg).into())
+            }
+            _ => {
+                let error = data.error.unwrap();
+                Err(MoxenError::ApiError(error).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_only_unreachable_registries() {
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(is_transient_error(&err));
+
+        let err = client.get("not a url").send().await.unwrap_err();
+        assert!(!is_transient_error(&err));
+    }
+
+    #[test]
+    fn retries_server_errors_and_rate_limits() {
+        assert!(is_transient_status(StatusCode::BAD_GATEWAY));
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+        assert!(!is_transient_status(StatusCode::UNAUTHORIZED));
+    }
+}
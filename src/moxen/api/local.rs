@@ -0,0 +1,729 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::transfer::{DownloadedPackage, PackageWriter};
+use super::{PackageSignature, RegistryBackend, SearchQuery};
+use crate::{
+    common::{MoxenError, PackageChecksums},
+    moxen::{auth::verify_signature, manifest::NormalizedManifest},
+};
+
+static UNVERSIONED: &str = "unversioned";
+static RECOVERY_CODE_COUNT: usize = 8;
+
+/// Registry kept in a plain directory, for offline work and tests.
+///
+/// Layout:
+///
+/// ```text
+/// <root>/<package>/<version>.mox     package tarball
+/// <root>/<package>/<version>.toml    normalized manifest
+/// <root>/<package>/<version>.sig     publisher's signature of the package
+/// <root>/<package>/<version>.yanked  present while the version is yanked
+/// <root>/<package>/owners            usernames allowed to publish, one per line
+///                                    (the signers of its versions when missing)
+/// <root>/.users/<username>.toml      registered users
+/// <root>/.challenges/<challenge>     pending sign up / recovery challenges
+/// ```
+pub struct LocalRegistry {
+    root: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocalUser {
+    public_key: String,
+    /// SHA-256 of the API key
+    api_key: String,
+    /// SHA-256 of each unused recovery code
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingChallenge {
+    name: String,
+    key: String,
+}
+
+impl LocalRegistry {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn package_dir(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.root.join(name))
+    }
+
+    fn user_file(&self, name: &str) -> Result<PathBuf> {
+        validate_name(name)?;
+        Ok(self.root.join(".users").join(format!("{name}.toml")))
+    }
+
+    fn load_user(&self, name: &str) -> Result<Option<LocalUser>> {
+        let path = self.user_file(name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let user = toml::from_str(&contents)
+            .map_err(|e| MoxenError::LoadError(format!("invalid user record for {name} - {e}")))?;
+
+        Ok(Some(user))
+    }
+
+    fn write_user(&self, name: &str, user: &LocalUser) -> Result<()> {
+        let path = self.user_file(name)?;
+        std::fs::create_dir_all(self.root.join(".users"))?;
+        std::fs::write(path, toml::to_string(user)?)?;
+
+        Ok(())
+    }
+
+    /// Takes a pending challenge, checking that `signed` was signed by the key it was issued for
+    fn take_challenge(&self, challenge: &str, signed: &str) -> Result<PendingChallenge> {
+        validate_name(challenge)?;
+        let path = self.root.join(".challenges").join(challenge);
+        if !path.exists() {
+            anyhow::bail!(MoxenError::AuthError("unknown challenge".to_string()));
+        }
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        let pending: PendingChallenge = toml::from_str(&contents)
+            .map_err(|e| MoxenError::LoadError(format!("invalid challenge record - {e}")))?;
+
+        verify_signature(&pending.key, challenge, signed)?;
+
+        Ok(pending)
+    }
+
+    fn authenticate(&self, username: &str, api_key: &str) -> Result<()> {
+        let authorised = match self.load_user(username)? {
+            Some(user) => user.api_key == hash(api_key),
+            None => false,
+        };
+        if !authorised {
+            anyhow::bail!(MoxenError::ApiError("invalid api key".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Usernames allowed to publish `name`. Packages published before owners were
+    /// tracked have no owners file, their owners are whoever signed their versions.
+    fn load_owners(&self, name: &str) -> Result<Vec<String>> {
+        let path = self.package_dir(name)?.join("owners");
+        if !path.exists() {
+            return self.publishers(name);
+        }
+
+        let owners = std::fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        Ok(owners)
+    }
+
+    fn write_owners(&self, name: &str, owners: &[String]) -> Result<()> {
+        let mut contents = owners.join("\n");
+        contents.push('\n');
+        std::fs::write(self.package_dir(name)?.join("owners"), contents)?;
+
+        Ok(())
+    }
+
+    /// Every user who signed a stored version of `name`
+    fn publishers(&self, name: &str) -> Result<Vec<String>> {
+        let dir = self.package_dir(name)?;
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut publishers = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "sig") {
+                let contents = std::fs::read_to_string(&path)?;
+                if let Ok(signature) = toml::from_str::<PackageSignature>(&contents) {
+                    publishers.push(signature.publisher);
+                }
+            }
+        }
+        publishers.sort();
+        publishers.dedup();
+
+        Ok(publishers)
+    }
+
+    /// Checks that `username` may manage `name`. Anyone may claim a new package, but an
+    /// existing one without any known owner is left for a registry admin to assign.
+    fn authorise_owner(&self, name: &str, username: &str) -> Result<()> {
+        let owners = self.load_owners(name)?;
+        if owners.is_empty() && self.package_dir(name)?.exists() {
+            anyhow::bail!(MoxenError::AuthError(format!(
+                "{name} has no recorded owners, a registry admin must add them to its owners file"
+            )));
+        }
+        if !owners.is_empty() && !owners.iter().any(|owner| owner == username) {
+            anyhow::bail!(MoxenError::AuthError(format!(
+                "{username} is not an owner of {name}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Every published version of `name`, unversioned and yanked uploads excluded
+    fn published_versions(&self, name: &str) -> Result<Vec<String>> {
+        let dir = self.package_dir(name)?;
+        if !dir.exists() {
+            anyhow::bail!(MoxenError::ProjectNotFound(name.to_string()));
+        }
+
+        let mut versions = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "mox") {
+                if let Some(stem) = path.file_stem() {
+                    let stem = stem.to_string_lossy().into_owned();
+                    let yanked = with_suffix(&path.with_file_name(&stem), "yanked").exists();
+                    if stem != UNVERSIONED && !yanked {
+                        versions.push(stem);
+                    }
+                }
+            }
+        }
+        // Versions which aren't semver sort by name ahead of the rest, so the last is
+        // always the newest semver release
+        versions.sort_by_cached_key(|v| (Version::parse(v).ok(), v.clone()));
+
+        Ok(versions)
+    }
+
+    /// Path of a stored version of `name` (the latest when `None`) without its extension
+    fn version_path(&self, name: &str, version: Option<&str>) -> Result<PathBuf> {
+        let dir = self.package_dir(name)?;
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => match self.published_versions(name)?.pop() {
+                Some(version) => version,
+                None => UNVERSIONED.to_string(),
+            },
+        };
+        validate_name(&version)?;
+
+        let path = dir.join(&version);
+        if !with_suffix(&path, "mox").exists() {
+            anyhow::bail!(MoxenError::ProjectNotFound(format!("{name} {version}")));
+        }
+
+        Ok(path)
+    }
+
+    fn latest_manifest(&self, name: &str) -> Result<NormalizedManifest> {
+        let path = with_suffix(&self.version_path(name, None)?, "toml");
+        let contents = std::fs::read_to_string(path)?;
+        let manifest = toml::from_str(&contents)
+            .map_err(|e| MoxenError::LoadError(format!("invalid manifest for {name} - {e}")))?;
+
+        Ok(manifest)
+    }
+}
+
+#[async_trait]
+impl RegistryBackend for LocalRegistry {
+    async fn fetch_mox(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<(String, DownloadedPackage)> {
+        let path = self.version_path(name, version)?;
+        let manifest = std::fs::read_to_string(with_suffix(&path, "toml"))?;
+        let mut file = std::fs::File::open(with_suffix(&path, "mox"))?;
+        let mut writer = PackageWriter::new()?;
+        std::io::copy(&mut file, &mut writer)?;
+
+        Ok((manifest, writer.finish()?))
+    }
+
+    async fn fetch_manifest(&self, name: &str, version: Option<&str>) -> Result<String> {
+        let path = self.version_path(name, version)?;
+        let manifest = std::fs::read_to_string(with_suffix(&path, "toml"))?;
+
+        Ok(manifest)
+    }
+
+    async fn fetch_versions(&self, name: &str) -> Result<Vec<String>> {
+        self.published_versions(name)
+    }
+
+    async fn fetch_signature(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Option<PackageSignature>> {
+        let path = with_suffix(&self.version_path(name, version)?, "sig");
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let signature = toml::from_str(&contents)
+            .map_err(|e| MoxenError::LoadError(format!("invalid signature for {name} - {e}")))?;
+
+        Ok(Some(signature))
+    }
+
+    async fn fetch_public_key(&self, username: &str) -> Result<String> {
+        match self.load_user(username)? {
+            Some(user) => Ok(user.public_key),
+            None => Err(MoxenError::ApiError(format!("no user named {username}")).into()),
+        }
+    }
+
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<NormalizedManifest>> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut results = vec![];
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_dir() || name.starts_with('.') {
+                continue;
+            }
+
+            // Packages with every version yanked have nothing left to install
+            let manifest = match self.latest_manifest(&name) {
+                Ok(manifest) => manifest,
+                Err(err) if matches!(err.downcast_ref(), Some(MoxenError::ProjectNotFound(_))) => {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            if query.matches(&manifest) {
+                results.push(manifest);
+            }
+        }
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(results)
+    }
+
+    async fn publish_mox_package(
+        &self,
+        manifest: &NormalizedManifest,
+        package: &Path,
+        signature: &PackageSignature,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        self.authenticate(username, api_key)?;
+        let contents = toml::to_string(manifest)?;
+        let public_key = self.fetch_public_key(username).await?;
+        verify_signature(
+            &public_key,
+            &manifest.signed_payload()?,
+            &signature.signature,
+        )?;
+        let checksums = PackageChecksums::of_file(package)?;
+        checksums.verify(&manifest.cksum)?;
+        checksums.verify(manifest.strongest_checksum())?;
+
+        let dir = self.package_dir(&manifest.name)?;
+        let version = manifest.version.as_deref().unwrap_or(UNVERSIONED);
+        validate_name(version)?;
+        let package_file = dir.join(format!("{version}.mox"));
+        if package_file.exists() {
+            anyhow::bail!(MoxenError::ProjectAlreadyExists);
+        }
+
+        self.authorise_owner(&manifest.name, username)?;
+        std::fs::create_dir_all(&dir)?;
+        if self.load_owners(&manifest.name)?.is_empty() {
+            self.write_owners(&manifest.name, &[username.to_string()])?;
+        }
+        std::fs::write(dir.join(format!("{version}.toml")), contents)?;
+        let signature = PackageSignature {
+            publisher: username.to_string(),
+            signature: signature.signature.clone(),
+        };
+        std::fs::write(
+            dir.join(format!("{version}.sig")),
+            toml::to_string(&signature)?,
+        )?;
+        std::fs::copy(package, package_file)?;
+
+        Ok(())
+    }
+
+    async fn yank(
+        &self,
+        name: &str,
+        version: &str,
+        yanked: bool,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        self.authenticate(username, api_key)?;
+        self.authorise_owner(name, username)?;
+        let marker = with_suffix(&self.version_path(name, Some(version))?, "yanked");
+        if yanked {
+            std::fs::write(marker, username)?;
+        } else if marker.exists() {
+            std::fs::remove_file(marker)?;
+        }
+
+        Ok(())
+    }
+
+    async fn owners(&self, name: &str) -> Result<Vec<String>> {
+        if !self.package_dir(name)?.exists() {
+            anyhow::bail!(MoxenError::ProjectNotFound(name.to_string()));
+        }
+
+        self.load_owners(name)
+    }
+
+    async fn add_owner(
+        &self,
+        name: &str,
+        owner: &str,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        self.authenticate(username, api_key)?;
+        if !self.package_dir(name)?.exists() {
+            anyhow::bail!(MoxenError::ProjectNotFound(name.to_string()));
+        }
+        self.authorise_owner(name, username)?;
+        if self.load_user(owner)?.is_none() {
+            anyhow::bail!(MoxenError::ApiError(format!("no user named {owner}")));
+        }
+
+        let mut owners = self.load_owners(name)?;
+        if !owners.iter().any(|o| o == owner) {
+            owners.push(owner.to_string());
+        }
+
+        self.write_owners(name, &owners)
+    }
+
+    async fn remove_owner(
+        &self,
+        name: &str,
+        owner: &str,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()> {
+        self.authenticate(username, api_key)?;
+        if !self.package_dir(name)?.exists() {
+            anyhow::bail!(MoxenError::ProjectNotFound(name.to_string()));
+        }
+        self.authorise_owner(name, username)?;
+
+        let mut owners = self.load_owners(name)?;
+        if !owners.iter().any(|o| o == owner) {
+            anyhow::bail!(MoxenError::ApiError(format!(
+                "{owner} is not an owner of {name}"
+            )));
+        }
+        if owners.len() == 1 {
+            anyhow::bail!(MoxenError::ApiError(format!(
+                "cannot remove the last owner of {name}"
+            )));
+        }
+        owners.retain(|o| o != owner);
+
+        self.write_owners(name, &owners)
+    }
+
+    async fn generate_challenge(&self, name: &str, pub_key: &str) -> Result<String> {
+        validate_name(name)?;
+        let challenge = random_token()?;
+        let pending = PendingChallenge {
+            name: name.to_string(),
+            key: pub_key.to_string(),
+        };
+
+        let dir = self.root.join(".challenges");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(&challenge), toml::to_string(&pending)?)?;
+
+        Ok(challenge)
+    }
+
+    async fn signup(&self, original: String, challenge: String) -> Result<(String, Vec<String>)> {
+        let pending = self.take_challenge(&original, &challenge)?;
+        if self.load_user(&pending.name)?.is_some() {
+            anyhow::bail!(MoxenError::ApiError(format!(
+                "username {} is already taken",
+                pending.name
+            )));
+        }
+
+        let api_key = random_token()?;
+        let recovery_codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| random_token())
+            .collect::<Result<Vec<String>, _>>()?;
+        let user = LocalUser {
+            public_key: pending.key,
+            api_key: hash(&api_key),
+            recovery_codes: recovery_codes.iter().map(|code| hash(code)).collect(),
+        };
+        self.write_user(&pending.name, &user)?;
+
+        Ok((api_key, recovery_codes))
+    }
+
+    async fn recover(&self, challenge: String, signed: String, code: String) -> Result<String> {
+        let pending = self.take_challenge(&challenge, &signed)?;
+        let Some(mut user) = self.load_user(&pending.name)? else {
+            anyhow::bail!(MoxenError::AuthError(format!(
+                "no user named {}",
+                pending.name
+            )));
+        };
+
+        let code = hash(&code);
+        let Some(idx) = user.recovery_codes.iter().position(|c| *c == code) else {
+            anyhow::bail!(MoxenError::AuthError("invalid recovery code".to_string()));
+        };
+
+        let api_key = random_token()?;
+        user.recovery_codes.remove(idx);
+        user.public_key = pending.key;
+        user.api_key = hash(&api_key);
+        self.write_user(&pending.name, &user)?;
+
+        Ok(api_key)
+    }
+}
+
+/// Keeps user supplied names from escaping the registry directory
+fn validate_name(name: &str) -> Result<(), MoxenError> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.contains(['/', '\\'])
+        || Path::new(name).is_absolute()
+    {
+        return Err(MoxenError::GeneralError(format!(
+            "invalid name for a local registry entry - {name}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Appends an extension, unlike `Path::with_extension` which would eat a version's patch number
+fn with_suffix(path: &Path, ext: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    path.into()
+}
+
+fn random_token() -> Result<String, MoxenError> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|e| MoxenError::GeneralError(e.to_string()))?;
+
+    Ok(hex::encode(bytes))
+}
+
+fn hash(value: &str) -> String {
+    hex::encode(digest(&SHA256, value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moxen::auth::{generate_keyfile_pair, MoxenKeyPair};
+
+    struct TestUser {
+        name: String,
+        keypair: MoxenKeyPair,
+        api_key: String,
+    }
+
+    async fn register(registry: &LocalRegistry, name: &str) -> TestUser {
+        let keypair = generate_keyfile_pair(&mut None).unwrap();
+        let challenge = registry
+            .generate_challenge(name, &keypair.public_key_as_string())
+            .await
+            .unwrap();
+        let signed = keypair.sign_message(&challenge);
+        let (api_key, _) = registry.signup(challenge, signed).await.unwrap();
+
+        TestUser {
+            name: name.to_string(),
+            keypair,
+            api_key,
+        }
+    }
+
+    async fn publish(
+        registry: &LocalRegistry,
+        user: &TestUser,
+        name: &str,
+        version: &str,
+    ) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let package = dir.path().join("package.mox");
+        std::fs::write(&package, format!("{name} {version}"))?;
+        let checksums = PackageChecksums::of_file(&package)?;
+        let manifest: NormalizedManifest = toml::from_str(&format!(
+            "name = \"{name}\"\nversion = \"{version}\"\nwow_version = \"11.0.2\"\ncategories = []\ncksum = \"{}\"\nchecksum = \"{}\"\n",
+            checksums.sha1, checksums.sha256
+        ))?;
+        let signature = PackageSignature {
+            publisher: user.name.clone(),
+            signature: user.keypair.sign_message(&manifest.signed_payload()?),
+        };
+
+        registry
+            .publish_mox_package(&manifest, &package, &signature, &user.api_key, &user.name)
+            .await
+    }
+
+    #[tokio::test]
+    async fn search_skips_fully_yanked_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LocalRegistry::new(dir.path());
+        let alice = register(&registry, "alice").await;
+        publish(&registry, &alice, "ace3", "1.0.0").await.unwrap();
+        publish(&registry, &alice, "ace3", "1.1.0").await.unwrap();
+        publish(&registry, &alice, "zlib", "1.0.0").await.unwrap();
+
+        for version in ["1.0.0", "1.1.0"] {
+            registry
+                .yank("ace3", version, true, &alice.api_key, "alice")
+                .await
+                .unwrap();
+        }
+
+        let results = registry.search(&SearchQuery::default()).await.unwrap();
+        let names: Vec<_> = results.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["zlib"]);
+    }
+
+    #[test]
+    fn orders_published_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LocalRegistry::new(dir.path());
+        let package = dir.path().join("ace3");
+        std::fs::create_dir_all(&package).unwrap();
+        for version in ["1.10.0", "r42", "1.9.0", "2.0.0-beta", "beta", UNVERSIONED] {
+            std::fs::write(package.join(format!("{version}.mox")), "").unwrap();
+        }
+
+        assert_eq!(
+            registry.published_versions("ace3").unwrap(),
+            vec!["beta", "r42", "1.9.0", "1.10.0", "2.0.0-beta"]
+        );
+        assert!(registry
+            .version_path("ace3", None)
+            .unwrap()
+            .ends_with("2.0.0-beta"));
+    }
+
+    fn is_auth_error(err: &anyhow::Error) -> bool {
+        matches!(err.downcast_ref(), Some(MoxenError::AuthError(_)))
+    }
+
+    #[tokio::test]
+    async fn only_owners_publish() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LocalRegistry::new(dir.path());
+        let alice = register(&registry, "alice").await;
+        let bob = register(&registry, "bob").await;
+        publish(&registry, &alice, "ace3", "1.0.0").await.unwrap();
+
+        let err = publish(&registry, &bob, "ace3", "1.1.0").await.unwrap_err();
+        assert!(is_auth_error(&err));
+        let err = registry
+            .add_owner("ace3", "bob", &bob.api_key, "bob")
+            .await
+            .unwrap_err();
+        assert!(is_auth_error(&err));
+
+        registry
+            .add_owner("ace3", "bob", &alice.api_key, "alice")
+            .await
+            .unwrap();
+        assert_eq!(registry.owners("ace3").await.unwrap(), vec!["alice", "bob"]);
+        // Bob publishes with his own key
+        publish(&registry, &bob, "ace3", "1.1.0").await.unwrap();
+        let signature = registry
+            .fetch_signature("ace3", Some("1.1.0"))
+            .await
+            .unwrap();
+        assert_eq!(signature.unwrap().publisher, "bob");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LocalRegistry::new(dir.path());
+        let alice = register(&registry, "alice").await;
+        register(&registry, "bob").await;
+        publish(&registry, &alice, "ace3", "1.0.0").await.unwrap();
+        registry
+            .add_owner("ace3", "bob", &alice.api_key, "alice")
+            .await
+            .unwrap();
+
+        registry
+            .remove_owner("ace3", "bob", &alice.api_key, "alice")
+            .await
+            .unwrap();
+        assert!(registry
+            .remove_owner("ace3", "alice", &alice.api_key, "alice")
+            .await
+            .is_err());
+        assert_eq!(registry.owners("ace3").await.unwrap(), vec!["alice"]);
+    }
+
+    #[tokio::test]
+    async fn packages_without_an_owners_file_belong_to_their_signers() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LocalRegistry::new(dir.path());
+        let alice = register(&registry, "alice").await;
+        let mallory = register(&registry, "mallory").await;
+        publish(&registry, &alice, "ace3", "1.0.0").await.unwrap();
+        std::fs::remove_file(dir.path().join("ace3").join("owners")).unwrap();
+
+        assert_eq!(registry.owners("ace3").await.unwrap(), vec!["alice"]);
+        let err = registry
+            .add_owner("ace3", "mallory", &mallory.api_key, "mallory")
+            .await
+            .unwrap_err();
+        assert!(is_auth_error(&err));
+        let err = publish(&registry, &mallory, "ace3", "1.1.0")
+            .await
+            .unwrap_err();
+        assert!(is_auth_error(&err));
+
+        // Without any signatures either, only a registry admin can assign owners
+        std::fs::remove_file(dir.path().join("ace3").join("1.0.0.sig")).unwrap();
+        let err = registry
+            .add_owner("ace3", "mallory", &alice.api_key, "alice")
+            .await
+            .unwrap_err();
+        assert!(is_auth_error(&err));
+    }
+}
@@ -1,134 +1,281 @@
+pub mod http;
+pub mod local;
 pub mod response;
-use response::DownloadPackageResponse;
+pub mod transfer;
 
 use anyhow::Result;
-use reqwest::Client;
-use reqwest::StatusCode;
-use response::UserRecoveryResponse;
-use response::UserRegisterResponse;
-use std::collections::HashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use crate::common::MoxenError;
+use super::config::NetworkConfig;
+use super::manifest::{MoxCategory, NormalizedManifest};
+use http::HttpRegistry;
+use local::LocalRegistry;
+use transfer::DownloadedPackage;
 
 pub const API_URL: &str = "https://localhost:9443";
 
-fn generate_request_client() -> Result<Client> {
-    let client = reqwest::Client::builder()
-        .use_rustls_tls()
-        .danger_accept_invalid_certs(true)
-        .build()?;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registry {
+    pub name: String,
+    pub url: String,
+    /// PEM bundle trusted in addition to the built-in roots
+    pub ca_bundle: Option<PathBuf>,
+    pub accept_invalid_certs: bool,
+    pub network: NetworkConfig,
+    /// Keys of the publishers trusted on this registry, by username
+    pub publisher_keys: BTreeMap<String, String>,
+}
 
-    Ok(client)
+/// Publisher's Ed25519 signature over the normalized manifest of a package version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageSignature {
+    pub publisher: String,
+    pub signature: String,
 }
 
-pub async fn fetch_mox(name: &str) -> Result<(String, Vec<u8>)> {
-    let url = format!("{API_URL}/api/v1/mox/{name}");
-    let client = generate_request_client()?;
+/// Key a package signature was checked against
+#[derive(Debug, Clone)]
+pub struct PublisherKey {
+    pub publisher: String,
+    pub key: String,
+}
 
-    let response = client.get(url).send().await?;
-    let status = response.status();
-    let response = response.json::<DownloadPackageResponse>().await?;
-    match status {
-        StatusCode::OK => {
-            let manifest = response.manifest;
-            let package = response.package;
-            return Ok((manifest, package));
-        }
-        StatusCode::NOT_FOUND => {
-            let error_message = response.error.unwrap();
-            Err(MoxenError::ProjectNotFound(error_message).into())
-        }
-        _ => {
-            let error_message = response.error.unwrap();
-            return Err(MoxenError::ApiError(error_message).into());
+/// Filters for a registry search. Every given filter has to match.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    /// Words looked for in the package name and description
+    pub terms: Vec<String>,
+    pub category: Option<MoxCategory>,
+    pub wow_version: Option<String>,
+    pub author: Option<String>,
+}
+
+/// Operations every registry backend has to support
+#[async_trait]
+pub trait RegistryBackend: Send + Sync {
+    /// Returns the normalized manifest (as TOML) and the `.mox` tarball for a package
+    async fn fetch_mox(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<(String, DownloadedPackage)>;
+
+    /// Returns only the normalized manifest (as TOML), leaving the tarball on the registry
+    async fn fetch_manifest(&self, name: &str, version: Option<&str>) -> Result<String>;
+
+    async fn fetch_versions(&self, name: &str) -> Result<Vec<String>>;
+
+    /// Signature uploaded with a package version, `None` for unsigned packages
+    async fn fetch_signature(
+        &self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Option<PackageSignature>>;
+
+    /// Base64 Ed25519 public key registered for `username`
+    async fn fetch_public_key(&self, username: &str) -> Result<String>;
+
+    /// Manifests of the latest version of every package matching `query`
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<NormalizedManifest>>;
+
+    async fn publish_mox_package(
+        &self,
+        manifest: &NormalizedManifest,
+        package: &Path,
+        signature: &PackageSignature,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()>;
+
+    /// Marks a published version as yanked (or restores it when `yanked` is false).
+    /// Yanked versions are left out of `fetch_versions` but can still be fetched directly.
+    async fn yank(
+        &self,
+        name: &str,
+        version: &str,
+        yanked: bool,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()>;
+
+    async fn owners(&self, name: &str) -> Result<Vec<String>>;
+
+    /// Lets `owner` publish new versions of `name`. Only existing owners may do this.
+    async fn add_owner(&self, name: &str, owner: &str, api_key: &str, username: &str)
+        -> Result<()>;
+
+    async fn remove_owner(
+        &self,
+        name: &str,
+        owner: &str,
+        api_key: &str,
+        username: &str,
+    ) -> Result<()>;
+
+    async fn generate_challenge(&self, name: &str, pub_key: &str) -> Result<String>;
+
+    async fn signup(&self, original: String, challenge: String) -> Result<(String, Vec<String>)>;
+
+    async fn recover(&self, challenge: String, signed: String, code: String) -> Result<String>;
+}
+
+impl SearchQuery {
+    pub fn matches(&self, manifest: &NormalizedManifest) -> bool {
+        let haystack = format!("{} {}", manifest.name, manifest.description).to_lowercase();
+        let terms = self
+            .terms
+            .iter()
+            .all(|term| haystack.contains(&term.to_lowercase()));
+
+        let category = self
+            .category
+            .is_none_or(|category| manifest.categories.contains(&category));
+
+        let wow_version = self.wow_version.as_ref().is_none_or(|version| {
+            manifest
+                .wow_version
+                .split(',')
+                .any(|supported| supported.trim().starts_with(version.as_str()))
+        });
+
+        let author = self.author.as_ref().is_none_or(|author| {
+            let author = author.to_lowercase();
+            manifest
+                .authors
+                .iter()
+                .any(|a| a.to_lowercase().contains(&author))
+        });
+
+        terms && category && wow_version && author
+    }
+}
+
+impl Registry {
+    /// Picks the backend for this registry from its URL scheme
+    pub fn backend(&self) -> Result<Box<dyn RegistryBackend>> {
+        match self.url.strip_prefix("file://") {
+            Some(root) => Ok(Box::new(LocalRegistry::new(root))),
+            None => Ok(Box::new(HttpRegistry::new(self)?)),
         }
     }
 }
 
+pub async fn fetch_mox(
+    registry: &Registry,
+    name: &str,
+    version: Option<&str>,
+) -> Result<(String, DownloadedPackage)> {
+    registry.backend()?.fetch_mox(name, version).await
+}
+
+pub async fn fetch_manifest(
+    registry: &Registry,
+    name: &str,
+    version: Option<&str>,
+) -> Result<String> {
+    registry.backend()?.fetch_manifest(name, version).await
+}
+
+pub async fn fetch_versions(registry: &Registry, name: &str) -> Result<Vec<String>> {
+    registry.backend()?.fetch_versions(name).await
+}
+
+pub async fn fetch_signature(
+    registry: &Registry,
+    name: &str,
+    version: Option<&str>,
+) -> Result<Option<PackageSignature>> {
+    registry.backend()?.fetch_signature(name, version).await
+}
+
+pub async fn fetch_public_key(registry: &Registry, username: &str) -> Result<String> {
+    registry.backend()?.fetch_public_key(username).await
+}
+
+pub async fn search(registry: &Registry, query: &SearchQuery) -> Result<Vec<NormalizedManifest>> {
+    registry.backend()?.search(query).await
+}
+
 pub async fn publish_mox_package(
-    body: HashMap<String, String>,
+    registry: &Registry,
+    manifest: &NormalizedManifest,
+    package: &Path,
+    signature: &PackageSignature,
     api_key: &str,
     username: &str,
 ) -> Result<()> {
-    let client = generate_request_client()?;
-    let url = format!("{API_URL}/api/v1/mox/new");
-    let response = client
-        .post(url)
-        .json(&body)
-        .header("x-api-key", api_key)
-        .header("x-authorize-user", username)
-        .send()
-        .await?;
-
-    let status = response.status();
-    match status {
-        StatusCode::CREATED => Ok(()),
-        StatusCode::CONFLICT => Err(MoxenError::ProjectAlreadyExists.into()),
-        StatusCode::UNAUTHORIZED => Err(MoxenError::ApiError("invalid api key".to_string()).into()),
-        _ => {
-            let text = response.text().await?;
-            return Err(MoxenError::ApiError(text).into());
-        }
-    }
+    registry
+        .backend()?
+        .publish_mox_package(manifest, package, signature, api_key, username)
+        .await
 }
 
-pub async fn generate_challenge(name: &str, pub_key: &str) -> Result<String> {
-    let client = generate_request_client()?;
-    let url = format!("{API_URL}/api/v1/auth/challenge");
-    let mut body = HashMap::new();
-    body.insert("name", name);
-    body.insert("key", pub_key);
-
-    let response = client.post(url).json(&body).send().await?;
-    let status = response.status();
-    let text = response.text().await?;
-    match status {
-        StatusCode::OK => Ok(text),
-        _ => Err(MoxenError::ApiError(text).into()),
-    }
+pub async fn yank(
+    registry: &Registry,
+    name: &str,
+    version: &str,
+    yanked: bool,
+    api_key: &str,
+    username: &str,
+) -> Result<()> {
+    registry
+        .backend()?
+        .yank(name, version, yanked, api_key, username)
+        .await
 }
 
-pub async fn signup(original: String, challenge: String) -> Result<(String, Vec<String>)> {
-    let client = generate_request_client()?;
-    let url = format!("{API_URL}/api/v1/auth/register");
-    let mut body = HashMap::new();
-    body.insert("original", original);
-    body.insert("challenge", challenge);
+pub async fn owners(registry: &Registry, name: &str) -> Result<Vec<String>> {
+    registry.backend()?.owners(name).await
+}
 
-    let response = client.post(url).json(&body).send().await?;
-    let status = response.status();
-    let data = response.json::<UserRegisterResponse>().await?;
+pub async fn add_owner(
+    registry: &Registry,
+    name: &str,
+    owner: &str,
+    api_key: &str,
+    username: &str,
+) -> Result<()> {
+    registry
+        .backend()?
+        .add_owner(name, owner, api_key, username)
+        .await
+}
 
-    match status {
-        StatusCode::CREATED => Ok((data.api_key, data.recovery_codes)),
-        _ => {
-            let error = data.error.unwrap();
-            return Err(MoxenError::ApiError(error).into());
-        }
-    }
+pub async fn remove_owner(
+    registry: &Registry,
+    name: &str,
+    owner: &str,
+    api_key: &str,
+    username: &str,
+) -> Result<()> {
+    registry
+        .backend()?
+        .remove_owner(name, owner, api_key, username)
+        .await
 }
 
-pub async fn recover(challenge: String, signed: String, code: String) -> Result<String> {
-    let client = generate_request_client()?;
-    let url = format!("{API_URL}/api/v1/auth/recovery");
-    let mut body = HashMap::new();
-    body.insert("challenge", challenge);
-    body.insert("signed", signed);
-    body.insert("code", code);
+pub async fn generate_challenge(registry: &Registry, name: &str, pub_key: &str) -> Result<String> {
+    registry.backend()?.generate_challenge(name, pub_key).await
+}
 
-    let response = client.post(url).json(&body).send().await?;
-    let status = response.status();
-    let data = response.json::<UserRecoveryResponse>().await?;
+pub async fn signup(
+    registry: &Registry,
+    original: String,
+    challenge: String,
+) -> Result<(String, Vec<String>)> {
+    registry.backend()?.signup(original, challenge).await
+}
 
-    match status {
-        StatusCode::OK => Ok(data.api_key),
-        StatusCode::UNAUTHORIZED => {
-            let error_msg = data.error.unwrap();
-            Err(MoxenError::AuthError(error_msg).into())
-        }
-        _ => {
-            let error = data.error.unwrap();
-            Err(MoxenError::ApiError(error).into())
-        }
-    }
+pub async fn recover(
+    registry: &Registry,
+    challenge: String,
+    signed: String,
+    code: String,
+) -> Result<String> {
+    registry.backend()?.recover(challenge, signed, code).await
 }
@@ -1,9 +1,46 @@
 use serde::Deserialize;
 
+use crate::moxen::manifest::NormalizedManifest;
+
 #[derive(Deserialize)]
-pub struct DownloadPackageResponse {
+pub struct PackageManifestResponse {
+    #[serde(default)]
     pub manifest: String,
-    pub package: Vec<u8>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PackageVersionsResponse {
+    #[serde(default)]
+    pub versions: Vec<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchResponse {
+    #[serde(default)]
+    pub results: Vec<NormalizedManifest>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PackageSignatureResponse {
+    pub publisher: Option<String>,
+    pub signature: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PublicKeyResponse {
+    #[serde(default)]
+    pub key: String,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PackageOwnersResponse {
+    #[serde(default)]
+    pub owners: Vec<String>,
     pub error: Option<String>,
 }
 
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use std::io::{IsTerminal, Write};
+use tempfile::NamedTempFile;
+
+use crate::common::{PackageChecksums, PackageHasher};
+
+/// A package tarball downloaded to a temporary file, which is removed again when dropped
+pub struct DownloadedPackage {
+    pub file: NamedTempFile,
+    pub checksums: PackageChecksums,
+}
+
+/// Streams a package to a temporary file, hashing it on the way
+pub struct PackageWriter {
+    file: NamedTempFile,
+    hasher: PackageHasher,
+    progress: Option<Progress>,
+}
+
+/// Download progress printed to stderr when it is a terminal
+struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+}
+
+impl DownloadedPackage {
+    pub fn reopen(&self) -> Result<std::fs::File> {
+        Ok(self.file.reopen()?)
+    }
+}
+
+impl PackageWriter {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            file: NamedTempFile::with_prefix("moxen-")?,
+            hasher: PackageHasher::default(),
+            progress: None,
+        })
+    }
+
+    /// Shows a progress line for `label`, sized by `total` bytes when known
+    pub fn with_progress(mut self, label: &str, total: Option<u64>) -> Self {
+        if std::io::stderr().is_terminal() {
+            self.progress = Some(Progress {
+                label: label.to_string(),
+                total,
+                done: 0,
+            });
+        }
+        self
+    }
+
+    /// Gives up on a partial download, clearing its progress line
+    pub fn discard(self) {
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+    }
+
+    pub fn finish(mut self) -> Result<DownloadedPackage> {
+        self.file.flush()?;
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+
+        Ok(DownloadedPackage {
+            file: self.file,
+            checksums: self.hasher.finalize(),
+        })
+    }
+}
+
+impl Write for PackageWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        if let Some(progress) = &mut self.progress {
+            progress.advance(written as u64);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Progress {
+    fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        let done = self.done / 1024;
+        match self.total {
+            Some(total) if total > 0 => eprint!(
+                "\rDownloading {} {done}/{} KiB ({}%)",
+                self.label,
+                total / 1024,
+                self.done * 100 / total
+            ),
+            _ => eprint!("\rDownloading {} {done} KiB", self.label),
+        }
+    }
+
+    fn finish(&self) {
+        // Clear the progress line so the rest of the output starts clean
+        eprint!("\r\x1b[2K");
+    }
+}
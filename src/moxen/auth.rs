@@ -1,11 +1,11 @@
 use base64::prelude::*;
 use ring::{
     rand::SystemRandom,
-    signature::{Ed25519KeyPair, KeyPair},
+    signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
 };
 use rustrict::CensorStr;
 
-use super::config::{MoxenConfig, MoxenCredentials};
+use super::config::MoxenCredentials;
 use crate::common::MoxenError;
 
 static MIN_USERNAME_LENGTH: usize = 3;
@@ -45,6 +45,20 @@ impl MoxenKeyPair {
     }
 }
 
+/// Checks a base64 Ed25519 `signature` of `msg` against a base64 public key
+pub fn verify_signature(public_key: &str, msg: &str, signature: &str) -> Result<(), MoxenError> {
+    let key = BASE64_STANDARD
+        .decode(public_key)
+        .map_err(|e| MoxenError::AuthError(e.to_string()))?;
+    let signature = BASE64_STANDARD
+        .decode(signature)
+        .map_err(|e| MoxenError::AuthError(e.to_string()))?;
+
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(msg.as_bytes(), &signature)
+        .map_err(|_| MoxenError::AuthError("signature is invalid".to_string()))
+}
+
 pub fn validate_username(name: &str) -> Result<(), MoxenError> {
     if name.len() < MIN_USERNAME_LENGTH {
         return Err(MoxenError::InvalidUsername(
@@ -68,8 +82,10 @@ pub fn validate_username(name: &str) -> Result<(), MoxenError> {
     Ok(())
 }
 
-pub fn generate_keyfile_pair(config: &mut MoxenConfig) -> Result<MoxenKeyPair, MoxenError> {
-    match config.credentials {
+pub fn generate_keyfile_pair(
+    credentials: &mut Option<MoxenCredentials>,
+) -> Result<MoxenKeyPair, MoxenError> {
+    match credentials {
         Some(_) => Err(MoxenError::ConfigError(
             "credentials already present, you are already registered as someone!".to_string(),
         )),
@@ -81,13 +97,11 @@ pub fn generate_keyfile_pair(config: &mut MoxenConfig) -> Result<MoxenKeyPair, M
             let keypair = MoxenKeyPair::new(document.as_ref())?;
 
             let private_key = BASE64_STANDARD.encode(document.as_ref());
-            let credentials = MoxenCredentials {
+            *credentials = Some(MoxenCredentials {
                 username: "".to_string(),
                 private_key,
                 api_key: None,
-            };
-
-            config.credentials = Some(credentials);
+            });
 
             Ok(keypair)
         }
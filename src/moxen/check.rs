@@ -0,0 +1,337 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    manifest::{PackageManifest, WowVersion},
+    toc::{self, Toc},
+};
+
+/// Client suffixes the game accepts on TOC file names, e.g. `MyAddon_Vanilla.toc`
+static FLAVOR_SUFFIXES: [&str; 9] = [
+    "Mainline", "Classic", "Vanilla", "TBC", "BCC", "Wrath", "WOTLKC", "Cata", "Mists",
+];
+
+#[derive(Debug)]
+pub enum Problem {
+    NoToc,
+    MissingFile((String, String, usize)),
+    UnlistedFile(String),
+    DuplicateEntry((String, String, usize)),
+    InterfaceMismatch((String, String, String)),
+    NameMismatch((String, String)),
+}
+
+/// Everything wrong with the TOC files of a project
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    problems: Vec<Problem>,
+}
+
+impl Problem {
+    /// Errors stop the addon from loading, everything else is a warning
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::NoToc | Self::MissingFile(_))
+    }
+}
+
+impl CheckReport {
+    pub fn errors(&self) -> usize {
+        self.problems.iter().filter(|p| p.is_error()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks every TOC in the root of the project in `dir` against the files on disk
+/// and Moxen.toml
+pub fn check_project(
+    dir: &Path,
+    manifest: &PackageManifest,
+    ignore: &[PathBuf],
+) -> Result<CheckReport> {
+    let mut report = CheckReport::default();
+    let mut tocs: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toc"))
+        .collect();
+    tocs.sort();
+
+    if tocs.is_empty() {
+        report.problems.push(Problem::NoToc);
+        return Ok(report);
+    }
+
+    // The checkout directory may be named anything, the installed folder comes from Moxen.toml
+    let folder = toc::toc_stem(manifest);
+    // Externals only exist once packaged and moved folders have TOCs of their own
+    let externals = toc::folder_prefixes(manifest.mox.externals.iter().flat_map(|e| e.keys()));
+    let moved = toc::folder_prefixes(manifest.mox.move_folders.iter().flat_map(|m| m.keys()));
+    let mut listed = BTreeSet::new();
+    for path in tocs {
+        let toc_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let (addon, suffix) = split_flavor_suffix(&stem);
+        if !addon.eq_ignore_ascii_case(&folder) {
+            report
+                .problems
+                .push(Problem::NameMismatch((toc_name.clone(), folder.clone())));
+        }
+
+        let parsed = Toc::load(&path)?;
+        check_interface(&mut report, &toc_name, &parsed, manifest, suffix);
+        check_entries(
+            &mut report,
+            dir,
+            &toc_name,
+            &parsed,
+            &externals,
+            &mut listed,
+        )?;
+    }
+
+    for file in toc::discover_files(dir, ignore)? {
+        let file_lower = file.to_lowercase();
+        let is_moved = moved.iter().any(|prefix| file_lower.starts_with(prefix));
+        if file.ends_with(".lua") && !is_moved && !listed.contains(&file_lower) {
+            report.problems.push(Problem::UnlistedFile(file));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Splits `MyAddon_Cata` into `MyAddon` and the `Cata` flavor suffix
+fn split_flavor_suffix(stem: &str) -> (&str, Option<&str>) {
+    match stem.rsplit_once('_') {
+        Some((addon, suffix))
+            if FLAVOR_SUFFIXES
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(suffix)) =>
+        {
+            (addon, Some(suffix))
+        }
+        _ => (stem, None),
+    }
+}
+
+/// Compares `## Interface:` with `wow_version`, or the interface of the matching flavor
+fn check_interface(
+    report: &mut CheckReport,
+    toc_name: &str,
+    toc: &Toc,
+    manifest: &PackageManifest,
+    suffix: Option<&str>,
+) {
+    let expected = match suffix {
+        None => manifest.wow_versions().ok(),
+        Some(suffix) => manifest
+            .mox
+            .flavors
+            .iter()
+            .flatten()
+            .find(|(kind, _)| kind.toc_suffix().eq_ignore_ascii_case(suffix))
+            .and_then(|(_, flavor)| WowVersion::parse_list(&flavor.interface).ok()),
+    };
+    // Nothing to compare against, bad versions in Moxen.toml are reported elsewhere
+    let Some(expected) = expected else {
+        return;
+    };
+
+    let found = toc.directive("Interface").unwrap_or_default();
+    let mut found_interfaces: Vec<u32> = WowVersion::parse_list(found)
+        .map(|versions| versions.iter().map(|v| v.interface()).collect())
+        .unwrap_or_default();
+    let mut expected_interfaces: Vec<u32> = expected.iter().map(|v| v.interface()).collect();
+    found_interfaces.sort();
+    expected_interfaces.sort();
+
+    if found_interfaces != expected_interfaces {
+        report.problems.push(Problem::InterfaceMismatch((
+            toc_name.to_string(),
+            found.to_string(),
+            toc::interface_line(&expected),
+        )));
+    }
+}
+
+/// Reports missing and duplicated files, recording every file the TOC loads in `listed`
+fn check_entries(
+    report: &mut CheckReport,
+    dir: &Path,
+    toc_name: &str,
+    toc: &Toc,
+    externals: &[String],
+    listed: &mut BTreeSet<String>,
+) -> Result<()> {
+    let mut seen: HashMap<(String, &[String]), usize> = HashMap::new();
+    for entry in toc.files.iter() {
+        let relative = entry.relative_path();
+        let key = (relative.to_lowercase(), entry.conditions.as_slice());
+        if seen.insert(key, entry.line).is_some() {
+            report.problems.push(Problem::DuplicateEntry((
+                toc_name.to_string(),
+                entry.path.clone(),
+                entry.line,
+            )));
+        }
+
+        let is_external = externals
+            .iter()
+            .any(|prefix| relative.to_lowercase().starts_with(prefix));
+        if entry.has_variables() || is_external {
+            continue;
+        }
+
+        let path = dir.join(&relative);
+        if !path.is_file() {
+            report.problems.push(Problem::MissingFile((
+                toc_name.to_string(),
+                entry.path.clone(),
+                entry.line,
+            )));
+            continue;
+        }
+
+        listed.insert(relative.to_lowercase());
+        if relative.ends_with(".xml") {
+            list_xml_includes(dir, &path, listed)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the files an XML file pulls in through `file="..."` attributes
+fn list_xml_includes(dir: &Path, xml: &Path, listed: &mut BTreeSet<String>) -> Result<()> {
+    let contents = std::fs::read_to_string(xml)?;
+    let parent = xml.parent().unwrap_or(dir);
+    for quote in ['"', '\''] {
+        let attribute = format!("file={quote}");
+        for (idx, _) in contents.match_indices(&attribute) {
+            let start = idx + attribute.len();
+            let Some(len) = contents[start..].find(quote) else {
+                continue;
+            };
+
+            let path = parent.join(contents[start..start + len].replace('\\', "/"));
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().to_lowercase();
+            if listed.insert(relative) && path.extension().is_some_and(|ext| ext == "xml") {
+                list_xml_includes(dir, &path, listed)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.is_error() { "error" } else { "warning" };
+        write!(f, "{level}: ")?;
+        match self {
+            Self::NoToc => write!(f, "no TOC file in the project root"),
+            Self::MissingFile((toc, file, line)) => {
+                write!(f, "{toc}:{line} lists `{file}` which does not exist")
+            }
+            Self::UnlistedFile(file) => write!(f, "`{file}` is not loaded by any TOC"),
+            Self::DuplicateEntry((toc, file, line)) => {
+                write!(f, "{toc}:{line} lists `{file}` more than once")
+            }
+            Self::InterfaceMismatch((toc, found, expected)) => write!(
+                f,
+                "{toc} has Interface `{found}` but Moxen.toml expects `{expected}`"
+            ),
+            Self::NameMismatch((toc, folder)) => write!(
+                f,
+                "{toc} does not match the folder name `{folder}`, the game will not load it"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "--- Check Report ---")?;
+        for problem in self.problems.iter() {
+            writeln!(f, "{problem}")?;
+        }
+        writeln!(f, "------")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(dir: &Path, toc_name: &str) -> PackageManifest {
+        let mut manifest = PackageManifest::fresh("My Addon");
+        manifest.mox.wow_version = "11.0.2".to_string();
+        std::fs::write(dir.join("Core.lua"), "").unwrap();
+        std::fs::write(
+            dir.join(toc_name),
+            "## Interface: 110002\nCore.lua\nMissing.lua\nCore.lua\n",
+        )
+        .unwrap();
+        manifest
+    }
+
+    #[test]
+    fn splits_flavor_suffixes() {
+        assert_eq!(
+            split_flavor_suffix("MyAddon_Cata"),
+            ("MyAddon", Some("Cata"))
+        );
+        assert_eq!(
+            split_flavor_suffix("MyAddon_vanilla"),
+            ("MyAddon", Some("vanilla"))
+        );
+        assert_eq!(split_flavor_suffix("My_Addon"), ("My_Addon", None));
+    }
+
+    #[test]
+    fn matches_tocs_against_the_manifest_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = project(dir.path(), "MyAddon.toc");
+
+        let report = check_project(dir.path(), &manifest, &[]).unwrap();
+        assert!(!report
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::NameMismatch(_))));
+
+        std::fs::rename(dir.path().join("MyAddon.toc"), dir.path().join("Other.toc")).unwrap();
+        let report = check_project(dir.path(), &manifest, &[]).unwrap();
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::NameMismatch((toc, folder)) if toc == "Other.toc" && folder == "MyAddon")));
+    }
+
+    #[test]
+    fn reports_missing_and_duplicate_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = project(dir.path(), "MyAddon.toc");
+
+        let report = check_project(dir.path(), &manifest, &[]).unwrap();
+        assert_eq!(report.errors(), 1);
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::MissingFile((_, file, 3)) if file == "Missing.lua")));
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::DuplicateEntry((_, file, 4)) if file == "Core.lua")));
+    }
+}
@@ -1,14 +1,61 @@
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
+use super::api::{PublisherKey, Registry, API_URL};
 use crate::common::MoxenError;
 
+pub const DEFAULT_REGISTRY: &str = "moxen";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MoxenConfig {
     #[serde(skip)]
     pub file_path: PathBuf,
 
+    /// Credentials for the default Moxen registry
     pub credentials: Option<MoxenCredentials>,
+
+    /// Registry used when none is given on the command line or in `MOXEN_REGISTRY`
+    pub default_registry: Option<String>,
+
+    /// Refuse to install packages without a valid publisher signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_signatures: bool,
+
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_default")]
+    pub network: NetworkConfig,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub registries: BTreeMap<String, RegistryConfig>,
+
+    /// Publisher keys pinned the first time a signed package of theirs was installed,
+    /// by registry then username. Signatures are only checked against these afterwards.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub publisher_keys: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Connection settings shared by every registry.
+///
+/// Without a `proxy` the `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` environment variables are used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Seconds to wait for a connection to the registry
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for the registry to send more data
+    pub read_timeout: u64,
+
+    /// How often a failed read-only request (fetch, search, challenge) is tried again
+    pub retries: u32,
+
+    /// Proxy for all registry traffic, e.g. `http://proxy.example.com:3128`
+    pub proxy: Option<String>,
+
+    /// Comma-separated hosts which bypass `proxy`
+    pub no_proxy: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -18,6 +65,19 @@ pub struct MoxenCredentials {
     pub api_key: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    pub url: String,
+    pub credentials: Option<MoxenCredentials>,
+
+    /// PEM bundle of extra CA certificates, for self-hosted registries
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Skips TLS certificate verification entirely. Only for local development!
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub danger_accept_invalid_certs: bool,
+}
+
 impl MoxenConfig {
     pub fn load(moxen_dir: impl AsRef<Path>) -> Result<Self, MoxenError> {
         let cfg_file = moxen_dir.as_ref().join("config");
@@ -25,6 +85,11 @@ impl MoxenConfig {
             let cfg = MoxenConfig {
                 file_path: cfg_file,
                 credentials: None,
+                default_registry: None,
+                require_signatures: false,
+                network: NetworkConfig::default(),
+                registries: BTreeMap::new(),
+                publisher_keys: BTreeMap::new(),
             };
 
             cfg.write()?;
@@ -48,4 +113,115 @@ impl MoxenConfig {
         std::fs::write(&self.file_path, contents)
             .map_err(|e| MoxenError::GeneralError(e.to_string()))
     }
+
+    /// Looks up a registry by its configured name or takes it as a URL.
+    ///
+    /// `None` falls back to the configured default registry, then the Moxen registry.
+    pub fn registry(&self, name: Option<&str>) -> Result<Registry, MoxenError> {
+        let name = name
+            .or(self.default_registry.as_deref())
+            .unwrap_or(DEFAULT_REGISTRY);
+
+        if let Some(registry) = self.registries.get(name) {
+            return Ok(Registry {
+                name: name.to_owned(),
+                url: registry.url.trim_end_matches('/').to_owned(),
+                ca_bundle: registry.ca_bundle.clone(),
+                accept_invalid_certs: registry.danger_accept_invalid_certs,
+                network: self.network.clone(),
+                publisher_keys: self.publisher_keys(name),
+            });
+        }
+
+        if name == DEFAULT_REGISTRY {
+            return Ok(Registry {
+                name: name.to_owned(),
+                url: API_URL.to_owned(),
+                ca_bundle: None,
+                accept_invalid_certs: false,
+                network: self.network.clone(),
+                publisher_keys: self.publisher_keys(name),
+            });
+        }
+
+        if name.contains("://") {
+            return Ok(Registry {
+                name: name.to_owned(),
+                url: name.trim_end_matches('/').to_owned(),
+                ca_bundle: None,
+                accept_invalid_certs: false,
+                network: self.network.clone(),
+                publisher_keys: self.publisher_keys(name),
+            });
+        }
+
+        Err(MoxenError::ConfigError(format!(
+            "unknown registry `{name}` - add it under [registries.{name}] in the Moxen config"
+        )))
+    }
+
+    fn publisher_keys(&self, registry: &str) -> BTreeMap<String, String> {
+        self.publisher_keys
+            .get(registry)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Pins the key of a publisher on `registry`, returning whether it wasn't pinned yet
+    pub fn trust_publisher(&mut self, registry: &Registry, publisher: &PublisherKey) -> bool {
+        let keys = self
+            .publisher_keys
+            .entry(registry.name.clone())
+            .or_default();
+        if keys.contains_key(&publisher.publisher) {
+            return false;
+        }
+
+        keys.insert(publisher.publisher.clone(), publisher.key.clone());
+        true
+    }
+
+    pub fn credentials(&self, registry: &Registry) -> Option<&MoxenCredentials> {
+        match self.registries.get(&registry.name) {
+            Some(cfg) => cfg.credentials.as_ref(),
+            None if registry.name == DEFAULT_REGISTRY => self.credentials.as_ref(),
+            None => None,
+        }
+    }
+
+    /// Credentials slot for `registry`, adding an entry for ad-hoc registry URLs
+    pub fn credentials_mut(&mut self, registry: &Registry) -> &mut Option<MoxenCredentials> {
+        if registry.name == DEFAULT_REGISTRY && !self.registries.contains_key(&registry.name) {
+            return &mut self.credentials;
+        }
+
+        &mut self
+            .registries
+            .entry(registry.name.clone())
+            .or_insert_with(|| RegistryConfig {
+                url: registry.url.clone(),
+                credentials: None,
+                ca_bundle: None,
+                danger_accept_invalid_certs: false,
+            })
+            .credentials
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            read_timeout: 30,
+            retries: 3,
+            proxy: None,
+            no_proxy: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
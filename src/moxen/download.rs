@@ -1,18 +1,56 @@
 use anyhow::Result;
-use std::path::PathBuf;
+use semver::{Version, VersionReq};
+use std::path::{Path, PathBuf};
 
 use crate::{
-    common::{untarball, validate_package_checksum},
-    moxen::{api, manifest::NormalizedManifest},
+    common::{untarball, MoxenError},
+    moxen::{
+        api::{self, transfer::DownloadedPackage, PublisherKey, Registry},
+        auth,
+        lockfile::LockedPackage,
+        manifest::{NormalizedManifest, PackageManifest},
+    },
 };
 
-pub async fn download_dependency(src_dir: PathBuf, dep: &str) -> Result<()> {
-    let libs_dir = src_dir.join(format!("libs/{dep}"));
-    if libs_dir.exists() {
-        return Ok(());
+/// A verified package from the registry, with the key its signature was checked
+/// against (`None` for unsigned packages)
+pub type FetchedPackage = (NormalizedManifest, DownloadedPackage, Option<PublisherKey>);
+
+pub async fn download_dependency(
+    registry: &Registry,
+    dep: &str,
+    req: &VersionReq,
+    locked: Option<&LockedPackage>,
+    require_signatures: bool,
+) -> Result<FetchedPackage> {
+    let locked = locked.filter(|pkg| lock_satisfies(pkg, req));
+    let version = match locked {
+        Some(pkg) => pkg.version.clone(),
+        None => resolve_version(registry, dep, req).await?,
+    };
+    let (manifest, package, publisher) =
+        download_version(registry, dep, version.as_deref(), require_signatures).await?;
+
+    // The pin may be a legacy SHA-1 checksum, so check it against the bytes rather
+    // than trusting the checksums the registry reports
+    if let Some(pkg) = locked {
+        if let Err(error) = package.checksums.verify(&pkg.cksum) {
+            eprintln!("Registry package for {dep} no longer matches Moxen.lock");
+            anyhow::bail!(error);
+        }
     }
 
-    let (manifest, package) = match api::fetch_mox(&dep).await {
+    Ok((manifest, package, publisher))
+}
+
+/// Fetches an exact version of `dep` (or the latest when `None`) and verifies it
+pub async fn download_version(
+    registry: &Registry,
+    dep: &str,
+    version: Option<&str>,
+    require_signatures: bool,
+) -> Result<FetchedPackage> {
+    let (manifest, package) = match api::fetch_mox(registry, dep, version).await {
         Ok((manifest, package)) => (manifest, package),
         Err(err) => {
             eprintln!("Error: {err}");
@@ -21,7 +59,7 @@ pub async fn download_dependency(src_dir: PathBuf, dep: &str) -> Result<()> {
     };
     let manifest = toml::from_str::<NormalizedManifest>(&manifest)?;
 
-    match validate_package_checksum(&package, &manifest.cksum) {
+    match package.checksums.verify(manifest.strongest_checksum()) {
         Ok(()) => {}
         Err(error) => {
             eprintln!("{error}");
@@ -29,11 +67,430 @@ pub async fn download_dependency(src_dir: PathBuf, dep: &str) -> Result<()> {
         }
     }
 
-    if !libs_dir.exists() {
-        std::fs::create_dir_all(&libs_dir)?;
+    let publisher =
+        match verify_package_signature(registry, dep, &manifest, require_signatures).await {
+            Ok(publisher) => publisher,
+            Err(error) => {
+                eprintln!("{error}");
+                anyhow::bail!(error);
+            }
+        };
+
+    Ok((manifest, package, publisher))
+}
+
+/// Checks the publisher's signature of the normalized manifest, which carries the checksum.
+///
+/// Publishers seen before are checked against the key pinned in the Moxen config, so a
+/// registry can't swap in a key of its own. Anyone else is trusted on first use with
+/// the key the registry has for them. A signature that doesn't verify is always an
+/// error, a missing one only when signatures are required.
+async fn verify_package_signature(
+    registry: &Registry,
+    dep: &str,
+    manifest: &NormalizedManifest,
+    require_signatures: bool,
+) -> Result<Option<PublisherKey>> {
+    let signature = api::fetch_signature(registry, dep, manifest.version.as_deref()).await?;
+    let Some(signature) = signature else {
+        if require_signatures {
+            anyhow::bail!(MoxenError::SignatureFailure((
+                dep.to_string(),
+                "package is not signed".to_string()
+            )));
+        }
+        return Ok(None);
+    };
+
+    let pinned = registry.publisher_keys.get(&signature.publisher);
+    let public_key = match pinned {
+        Some(key) => key.clone(),
+        None => api::fetch_public_key(registry, &signature.publisher).await?,
+    };
+    let payload = manifest.signed_payload()?;
+    if auth::verify_signature(&public_key, &payload, &signature.signature).is_err() {
+        let reason = match pinned {
+            Some(_) => format!(
+                "not signed by the key of {} pinned in the Moxen config",
+                signature.publisher
+            ),
+            None => format!(
+                "not signed by the registered key of {}",
+                signature.publisher
+            ),
+        };
+        anyhow::bail!(MoxenError::SignatureFailure((dep.to_string(), reason)));
+    }
+
+    Ok(Some(PublisherKey {
+        publisher: signature.publisher,
+        key: public_key,
+    }))
+}
+
+/// Unpacks `package` next to its final `libs/<dep>` location so that a failed
+/// extraction never touches what is already vendored
+pub fn stage_dependency(src_dir: &Path, dep: &str, package: &DownloadedPackage) -> Result<PathBuf> {
+    let staging_dir = src_dir.join("libs").join(format!(".{dep}.partial"));
+    if staging_dir.exists() {
+        std::fs::remove_dir_all(&staging_dir)?;
+    }
+
+    std::fs::create_dir_all(&staging_dir)?;
+    if let Err(err) = untarball(&staging_dir, || package.reopen()) {
+        discard_staged(&staging_dir)?;
+        anyhow::bail!(err);
+    }
+
+    Ok(staging_dir)
+}
+
+/// Swaps a staged package into `libs/<dep>`. Any previous version is kept aside until
+/// the install is confirmed with [`confirm_dependency`] or undone with [`rollback_dependency`].
+pub fn commit_dependency(src_dir: &Path, dep: &str, staged: &Path) -> Result<()> {
+    let libs_dir = libs_dir(src_dir, dep);
+    let previous_dir = previous_dir(src_dir, dep);
+    if previous_dir.exists() {
+        std::fs::remove_dir_all(&previous_dir)?;
+    }
+
+    if libs_dir.exists() {
+        std::fs::rename(&libs_dir, &previous_dir)?;
+    }
+    if let Err(err) = std::fs::rename(staged, &libs_dir) {
+        if previous_dir.exists() {
+            std::fs::rename(&previous_dir, &libs_dir)?;
+        }
+        anyhow::bail!(err);
+    }
+
+    Ok(())
+}
+
+/// Drops the previous version a committed package replaced
+pub fn confirm_dependency(src_dir: &Path, dep: &str, manifest: &NormalizedManifest) -> Result<()> {
+    let previous_dir = previous_dir(src_dir, dep);
+    if previous_dir.exists() {
+        std::fs::remove_dir_all(&previous_dir)?;
+    }
+
+    let libs_dir = libs_dir(src_dir, dep);
+    match &manifest.version {
+        Some(version) => println!("Adding {dep} v{version} to {}", libs_dir.display()),
+        None => println!("Adding {dep} to {}", libs_dir.display()),
+    }
+    Ok(())
+}
+
+/// Removes a committed package again, putting back the version it replaced
+pub fn rollback_dependency(src_dir: &Path, dep: &str) -> Result<()> {
+    let libs_dir = libs_dir(src_dir, dep);
+    if libs_dir.exists() {
+        std::fs::remove_dir_all(&libs_dir)?;
+    }
+
+    let previous_dir = previous_dir(src_dir, dep);
+    if previous_dir.exists() {
+        std::fs::rename(&previous_dir, &libs_dir)?;
+    }
+
+    Ok(())
+}
+
+fn previous_dir(src_dir: &Path, dep: &str) -> PathBuf {
+    src_dir.join("libs").join(format!(".{dep}.old"))
+}
+
+pub fn discard_staged(staged: &Path) -> Result<()> {
+    if staged.exists() {
+        std::fs::remove_dir_all(staged)?;
     }
-    untarball(&libs_dir, package)?;
 
-    println!("Adding {dep} to {}", libs_dir.display());
     Ok(())
 }
+
+pub fn libs_dir(src_dir: &Path, dep: &str) -> PathBuf {
+    src_dir.join("libs").join(dep)
+}
+
+/// Reads the manifest shipped inside an already vendored package, if it has one
+pub fn installed_manifest(src_dir: &Path, dep: &str) -> Option<PackageManifest> {
+    let dir = libs_dir(src_dir, dep);
+    if !dir.join("Moxen.toml").exists() {
+        return None;
+    }
+
+    PackageManifest::load(dir).ok()
+}
+
+/// Names of every package currently vendored in `libs/`
+pub fn installed_packages(src_dir: &Path) -> Result<Vec<String>> {
+    let libs = src_dir.join("libs");
+    if !libs.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut packages = vec![];
+    for entry in std::fs::read_dir(libs)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Skip staging directories left over from an interrupted install
+        if entry.file_type()?.is_dir() && !name.starts_with('.') {
+            packages.push(name);
+        }
+    }
+    packages.sort();
+
+    Ok(packages)
+}
+
+/// Vendored packages whose own manifest still depends on `dep`
+pub fn installed_dependents(src_dir: &Path, dep: &str) -> Result<Vec<String>> {
+    let dependents = installed_packages(src_dir)?
+        .into_iter()
+        .filter(|pkg| pkg != dep)
+        .filter(|pkg| {
+            installed_manifest(src_dir, pkg)
+                .is_some_and(|manifest| manifest.dependency(dep).is_some())
+        })
+        .collect();
+
+    Ok(dependents)
+}
+
+/// Drops any lines referencing `libs/<dep>` from the project's `embeds.xml`,
+/// returning whether the file changed
+pub fn unembed_dependency(src_dir: &Path, dep: &str) -> Result<bool> {
+    let embeds = src_dir.join("embeds.xml");
+    if !embeds.exists() {
+        return Ok(false);
+    }
+
+    let contents = std::fs::read_to_string(&embeds)?;
+    let prefixes = [
+        format!("libs\\{dep}\\").to_lowercase(),
+        format!("libs/{dep}/").to_lowercase(),
+    ];
+    let mut changed = false;
+    let mut kept = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let lower = line.to_lowercase();
+        if prefixes.iter().any(|prefix| lower.contains(prefix)) {
+            changed = true;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+
+    if changed {
+        std::fs::write(embeds, kept)?;
+    }
+
+    Ok(changed)
+}
+
+/// Checks whether a locked package can still be used for `req`
+pub fn lock_satisfies(pkg: &LockedPackage, req: &VersionReq) -> bool {
+    version_satisfies(pkg.version.as_deref(), req)
+}
+
+pub fn version_satisfies(version: Option<&str>, req: &VersionReq) -> bool {
+    match version {
+        Some(version) => Version::parse(version).is_ok_and(|v| req.matches(&v)),
+        None => *req == VersionReq::STAR,
+    }
+}
+
+/// Picks the newest published version of `dep` satisfying `req`.
+///
+/// Returns `None` for unversioned packages when any version is acceptable,
+/// in which case the registry serves whatever it has.
+pub async fn resolve_version(
+    registry: &Registry,
+    dep: &str,
+    req: &VersionReq,
+) -> Result<Option<String>> {
+    let published = api::fetch_versions(registry, dep).await?;
+    if published.is_empty() && *req == VersionReq::STAR {
+        return Ok(None);
+    }
+
+    let candidate = published
+        .iter()
+        .filter_map(|v| Version::parse(v).ok())
+        .filter(|v| req.matches(v))
+        .max();
+
+    match candidate {
+        Some(version) => Ok(Some(version.to_string())),
+        None => {
+            if published.is_empty() {
+                eprintln!("No published versions of {dep} are available");
+            } else {
+                eprintln!("Available versions of {dep}: {}", published.join(", "));
+            }
+            anyhow::bail!(MoxenError::NoMatchingVersion((
+                dep.to_string(),
+                req.to_string()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::PackageChecksums;
+    use crate::moxen::{
+        api::PackageSignature,
+        auth::{generate_keyfile_pair, MoxenKeyPair},
+        config::{MoxenConfig, NetworkConfig},
+    };
+    use std::collections::BTreeMap;
+
+    fn registry(dir: &Path) -> Registry {
+        Registry {
+            name: "test".to_string(),
+            url: format!("file://{}", dir.display()),
+            ca_bundle: None,
+            accept_invalid_certs: false,
+            network: NetworkConfig::default(),
+            publisher_keys: BTreeMap::new(),
+        }
+    }
+
+    async fn register(registry: &Registry, name: &str) -> (MoxenKeyPair, String) {
+        let keypair = generate_keyfile_pair(&mut None).unwrap();
+        let challenge = api::generate_challenge(registry, name, &keypair.public_key_as_string())
+            .await
+            .unwrap();
+        let signed = keypair.sign_message(&challenge);
+        let (api_key, _) = api::signup(registry, challenge, signed).await.unwrap();
+
+        (keypair, api_key)
+    }
+
+    /// Publishes a signed `ace3` v1.0.0 as alice, returning her key pair
+    async fn publish_ace3(registry: &Registry) -> MoxenKeyPair {
+        let (keypair, api_key) = register(registry, "alice").await;
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("ace3.mox");
+        std::fs::write(&package, "ace3").unwrap();
+        let checksums = PackageChecksums::of_file(&package).unwrap();
+        let manifest: NormalizedManifest = toml::from_str(&format!(
+            "name = \"ace3\"\nversion = \"1.0.0\"\nwow_version = \"11.0.2\"\ncategories = []\ncksum = \"{}\"\nchecksum = \"{}\"\n",
+            checksums.sha1, checksums.sha256
+        ))
+        .unwrap();
+        let signature = PackageSignature {
+            publisher: "alice".to_string(),
+            signature: keypair.sign_message(&manifest.signed_payload().unwrap()),
+        };
+        api::publish_mox_package(registry, &manifest, &package, &signature, &api_key, "alice")
+            .await
+            .unwrap();
+
+        keypair
+    }
+
+    fn is_signature_failure(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<MoxenError>(),
+            Some(MoxenError::SignatureFailure(_))
+        )
+    }
+
+    #[tokio::test]
+    async fn pins_publisher_keys_on_first_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = registry(dir.path());
+        let keypair = publish_ace3(&registry).await;
+
+        let (_, _, publisher) = download_version(&registry, "ace3", Some("1.0.0"), true)
+            .await
+            .unwrap();
+        let publisher = publisher.unwrap();
+        assert_eq!(publisher.publisher, "alice");
+        assert_eq!(publisher.key, keypair.public_key_as_string());
+
+        let mut config: MoxenConfig = toml::from_str("").unwrap();
+        assert!(config.trust_publisher(&registry, &publisher));
+        let other = generate_keyfile_pair(&mut None).unwrap();
+        let changed = PublisherKey {
+            publisher: "alice".to_string(),
+            key: other.public_key_as_string(),
+        };
+        assert!(!config.trust_publisher(&registry, &changed));
+        assert_eq!(
+            config.publisher_keys["test"]["alice"],
+            keypair.public_key_as_string()
+        );
+
+        // A registry handing out a new key for alice doesn't override the pinned one
+        registry
+            .publisher_keys
+            .insert("alice".to_string(), other.public_key_as_string());
+        let err = download_version(&registry, "ace3", Some("1.0.0"), false)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_signature_failure(&err));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = registry(dir.path());
+        publish_ace3(&registry).await;
+        let signature_path = dir.path().join("ace3").join("1.0.0.sig");
+        let manifest_path = dir.path().join("ace3").join("1.0.0.toml");
+
+        // Signed by someone other than the publisher it names
+        let mallory = generate_keyfile_pair(&mut None).unwrap();
+        let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+        let manifest: NormalizedManifest = toml::from_str(&manifest).unwrap();
+        let forged = PackageSignature {
+            publisher: "alice".to_string(),
+            signature: mallory.sign_message(&manifest.signed_payload().unwrap()),
+        };
+        let original = std::fs::read_to_string(&signature_path).unwrap();
+        std::fs::write(&signature_path, toml::to_string(&forged).unwrap()).unwrap();
+        let err = download_version(&registry, "ace3", Some("1.0.0"), false)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_signature_failure(&err));
+
+        // A manifest changed after signing
+        std::fs::write(&signature_path, original).unwrap();
+        let mut tampered = manifest;
+        tampered
+            .dependencies
+            .insert("evil".to_string(), "*".to_string());
+        std::fs::write(&manifest_path, toml::to_string(&tampered).unwrap()).unwrap();
+        let err = download_version(&registry, "ace3", Some("1.0.0"), false)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_signature_failure(&err));
+    }
+
+    #[tokio::test]
+    async fn requiring_signatures_refuses_unsigned_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = registry(dir.path());
+        publish_ace3(&registry).await;
+        std::fs::remove_file(dir.path().join("ace3").join("1.0.0.sig")).unwrap();
+
+        let (_, _, publisher) = download_version(&registry, "ace3", Some("1.0.0"), false)
+            .await
+            .unwrap();
+        assert!(publisher.is_none());
+        let err = download_version(&registry, "ace3", Some("1.0.0"), true)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_signature_failure(&err));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::common::MoxenError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::manifest::NormalizedManifest;
+
+static LOCKFILE: &str = "Moxen.lock";
+static LOCKFILE_HEADER: &str = "# This file is generated by Moxen. Do not edit it by hand.\n\n";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "package", skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Option<String>,
+    /// Strongest checksum of the package, `algo:hex` or a legacy SHA-1 hex string
+    pub cksum: String,
+}
+
+impl Lockfile {
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let path = dir.as_ref().join(LOCKFILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(&path).context("reading lockfile")?;
+        let lockfile: Lockfile = toml::from_str(&contents)
+            .map_err(|e| MoxenError::LoadError(format!("invalid Moxen.lock - {e}")))?;
+
+        Ok(lockfile)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let path = dir.as_ref().join(LOCKFILE);
+        let mut contents = LOCKFILE_HEADER.to_string();
+        contents.push_str(&toml::to_string(&self)?);
+        std::fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|pkg| pkg.name == name)
+    }
+
+    /// Drops the pin for `name`, returning whether the lockfile changed
+    pub fn unlock(&mut self, name: &str) -> bool {
+        let before = self.packages.len();
+        self.packages.retain(|pkg| pkg.name != name);
+        before != self.packages.len()
+    }
+
+    /// Whether `name` is pinned to the resolved manifest. Pins of the legacy SHA-1
+    /// checksum still count, downloads check the package bytes against them.
+    pub fn pins(&self, name: &str, manifest: &NormalizedManifest) -> bool {
+        self.get(name)
+            .is_some_and(|pkg| pkg.version == manifest.version && manifest.has_checksum(&pkg.cksum))
+    }
+
+    /// Pins `name` to the resolved manifest, returning whether the lockfile changed.
+    ///
+    /// Only pass manifests whose package was verified against any existing pin, as a
+    /// legacy SHA-1 pin is upgraded to the strongest checksum here.
+    pub fn lock(&mut self, name: &str, manifest: &NormalizedManifest) -> bool {
+        let locked = LockedPackage {
+            name: name.to_string(),
+            version: manifest.version.clone(),
+            cksum: manifest.strongest_checksum().to_owned(),
+        };
+
+        match self.packages.iter_mut().find(|pkg| pkg.name == name) {
+            Some(existing) if *existing == locked => false,
+            Some(existing) => {
+                *existing = locked;
+                true
+            }
+            None => {
+                self.packages.push(locked);
+                self.packages.sort_by(|a, b| a.name.cmp(&b.name));
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(version: &str, checksum: Option<&str>) -> NormalizedManifest {
+        let mut contents = format!(
+            "name = \"ace3\"\nversion = \"{version}\"\nwow_version = \"11.0.2\"\ncategories = []\ncksum = \"aaaa\"\n"
+        );
+        if let Some(checksum) = checksum {
+            contents.push_str(&format!("checksum = \"{checksum}\"\n"));
+        }
+        toml::from_str(&contents).unwrap()
+    }
+
+    #[test]
+    fn lock_pins_new_packages() {
+        let mut lockfile = Lockfile::default();
+        assert!(lockfile.lock("ace3", &manifest("1.0.0", Some("sha256:bbbb"))));
+
+        let locked = lockfile.get("ace3").unwrap();
+        assert_eq!(locked.version.as_deref(), Some("1.0.0"));
+        assert_eq!(locked.cksum, "sha256:bbbb");
+        assert!(!lockfile.lock("ace3", &manifest("1.0.0", Some("sha256:bbbb"))));
+    }
+
+    #[test]
+    fn lock_upgrades_legacy_checksum_pins() {
+        let mut lockfile = Lockfile {
+            packages: vec![LockedPackage {
+                name: "ace3".to_string(),
+                version: Some("1.0.0".to_string()),
+                cksum: "aaaa".to_string(),
+            }],
+        };
+        let manifest = manifest("1.0.0", Some("sha256:bbbb"));
+
+        assert!(lockfile.pins("ace3", &manifest));
+        assert!(lockfile.lock("ace3", &manifest));
+        assert_eq!(lockfile.get("ace3").unwrap().cksum, "sha256:bbbb");
+        assert!(!lockfile.lock("ace3", &manifest));
+    }
+
+    #[test]
+    fn pins_need_the_same_version_and_checksum() {
+        let mut lockfile = Lockfile::default();
+        lockfile.lock("ace3", &manifest("1.0.0", Some("sha256:bbbb")));
+
+        assert!(lockfile.pins("ace3", &manifest("1.0.0", Some("sha256:bbbb"))));
+        assert!(!lockfile.pins("ace3", &manifest("1.0.0", Some("sha256:cccc"))));
+        assert!(!lockfile.pins("ace3", &manifest("1.1.0", Some("sha256:bbbb"))));
+        assert!(!lockfile.pins("zlib", &manifest("1.0.0", Some("sha256:bbbb"))));
+    }
+
+    #[test]
+    fn lock_repins_changed_packages() {
+        let mut lockfile = Lockfile::default();
+        lockfile.lock("ace3", &manifest("1.0.0", Some("sha256:bbbb")));
+
+        assert!(lockfile.lock("ace3", &manifest("1.1.0", Some("sha256:cccc"))));
+        assert_eq!(lockfile.get("ace3").unwrap().cksum, "sha256:cccc");
+        assert!(lockfile.lock("ace3", &manifest("1.1.0", Some("sha256:dddd"))));
+        assert_eq!(lockfile.get("ace3").unwrap().cksum, "sha256:dddd");
+    }
+
+    #[test]
+    fn unlock_reports_changes() {
+        let mut lockfile = Lockfile::default();
+        lockfile.lock("ace3", &manifest("1.0.0", None));
+
+        assert!(lockfile.unlock("ace3"));
+        assert!(!lockfile.unlock("ace3"));
+    }
+}
@@ -1,7 +1,13 @@
-use crate::common::MoxenError;
+use crate::{
+    common::{contained_path, MoxenError},
+    DocumentationType,
+};
+
+use super::toc;
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use std::{fmt, fs, io::Write, path::Path};
+use semver::VersionReq;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::BTreeMap, fmt, fs, io::Write, path::Path, str::FromStr};
 
 static MANIFEST: &str = "Moxen.toml";
 
@@ -13,6 +19,9 @@ pub struct PackageManifest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Metadata {
     pub name: String,
+    /// Name shown in the in-game addon list, defaults to `name`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
     pub version: Option<String>,
     pub wow_version: String,
     pub description: String,
@@ -20,8 +29,79 @@ pub struct Metadata {
     pub authors: Vec<String>,
     pub homepage: Option<String>,
     pub repository: Option<String>,
-    pub dependencies: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_dependencies")]
+    pub dependencies: Option<BTreeMap<String, Dependency>>,
     pub ignore: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saved_variables: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saved_variables_per_character: Option<Vec<String>>,
+    /// Files listed in the generated TOC, in load order. Every Lua and XML file when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// Game clients with a TOC of their own, e.g. `[mox.flavors.classic]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavors: Option<BTreeMap<DocumentationType, Flavor>>,
+    /// Folder the addon is installed as, when it differs from the project directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package_as: Option<String>,
+    /// Git repositories cloned into the package, by their path in it (e.g. `libs/LibStub`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub externals: Option<BTreeMap<String, External>>,
+    /// Folders split out into packages of their own, from their path to the new addon name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub move_folders: Option<BTreeMap<String, String>>,
+    /// Also craft a `-nolib` package without the externals
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nolib: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum External {
+    Url(String),
+    Detailed(ExternalDetail),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalDetail {
+    pub url: String,
+    /// Tag to check out, `latest` for the most recent one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Repository type, only `git` is supported
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// How the TOC of one game client differs from the main one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Flavor {
+    /// WoW version(s) of the client, e.g. `4.4.0` or `40400`
+    pub interface: String,
+    /// Loaded after the shared files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Dependency {
+    Version(String),
+    Detailed(DependencyDetail),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyDetail {
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,11 +109,33 @@ pub struct NormalizedManifest {
     pub name: String,
     pub version: Option<String>,
     pub wow_version: String,
+    #[serde(default)]
+    pub description: String,
     pub categories: Vec<MoxCategory>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// SHA-1 of the package, kept so older clients can still verify it
     pub cksum: String,
+    /// Stronger `algo:hex` checksum (SHA-256) of the package, missing on older packages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+}
+
+/// A game client version, written as `11.0.1` in Moxen.toml and as `110001` in TOC files
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WowVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
 }
 
-#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
 #[serde(rename_all = "kebab-case")]
 pub enum MoxCategory {
     Achievements,
@@ -89,9 +191,10 @@ impl PackageManifest {
     }
 
     pub fn fresh(name: &str) -> Self {
-        let manifest = Self {
+        Self {
             mox: Metadata {
                 name: name.to_string(),
+                title: None,
                 version: Some("0.1.0".to_string()),
                 description: "Bootstrapped by Moxen".to_string(),
                 wow_version: "<Insert current WoW version here (11.0.1)!>".to_string(),
@@ -101,10 +204,16 @@ impl PackageManifest {
                 repository: None,
                 dependencies: None,
                 ignore: None,
+                saved_variables: None,
+                saved_variables_per_character: None,
+                files: None,
+                flavors: None,
+                package_as: None,
+                externals: None,
+                move_folders: None,
+                nolib: false,
             },
-        };
-
-        manifest
+        }
     }
 
     pub fn interactive(name: &str) -> PackageManifest {
@@ -145,7 +254,12 @@ impl PackageManifest {
     }
 
     pub fn normalise_name(&self, with_version: bool) -> String {
-        let mut name = self.mox.name.to_lowercase().replace(" ", "-");
+        self.normalise_name_as(&self.mox.name, with_version)
+    }
+
+    /// Normalises `name` as if it were the name of this package
+    pub fn normalise_name_as(&self, name: &str, with_version: bool) -> String {
+        let mut name = name.to_lowercase().replace(" ", "-");
         if !with_version {
             return name;
         }
@@ -160,8 +274,28 @@ impl PackageManifest {
         name
     }
 
-    pub fn normalise(self, cksum: String) -> NormalizedManifest {
+    /// Name of the addon folder, which its TOC files have to match
+    pub fn addon_folder(&self) -> &str {
+        self.mox.package_as.as_deref().unwrap_or(&self.mox.name)
+    }
+
+    /// Every WoW version in `wow_version`, which may list several separated by commas
+    pub fn wow_versions(&self) -> Result<Vec<WowVersion>, MoxenError> {
+        WowVersion::parse_list(&self.mox.wow_version).map_err(|_| {
+            MoxenError::ConfigError(format!(
+                "invalid wow_version `{}` in Moxen.toml - expected a WoW version such as 11.0.1 or 110001",
+                self.mox.wow_version
+            ))
+        })
+    }
+
+    pub fn normalise(self, cksum: String, checksum: String) -> NormalizedManifest {
         let name = self.normalise_name(false);
+        // Registries always see the dotted form, however Moxen.toml spells it
+        let wow_version = match self.wow_versions() {
+            Ok(versions) => WowVersion::join(&versions, |version| version.to_string()),
+            Err(_) => self.mox.wow_version,
+        };
         let categories = match self.mox.categories {
             Some(cat) => {
                 if !cat.is_empty() {
@@ -173,28 +307,243 @@ impl PackageManifest {
             None => vec![MoxCategory::Miscellaneous],
         };
 
+        let dependencies = self
+            .mox
+            .dependencies
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, dep)| {
+                let version = dep.version().to_owned();
+                (name, version)
+            })
+            .collect();
+
         NormalizedManifest {
             name,
             version: self.mox.version,
-            wow_version: self.mox.wow_version,
+            wow_version,
+            description: self.mox.description,
             categories,
+            authors: self.mox.authors,
+            homepage: self.mox.homepage,
+            repository: self.mox.repository,
             cksum,
+            checksum: Some(checksum),
+            dependencies,
         }
     }
 
-    pub fn add_dependency(&mut self, dep: String) {
-        if let Some(deps) = self.mox.dependencies.as_mut() {
-            if deps.contains(&dep) {
-                return;
-            }
-            deps.push(dep);
+    pub fn add_dependency(&mut self, name: String, dep: Dependency) {
+        let deps = self.mox.dependencies.get_or_insert_with(BTreeMap::new);
+        deps.insert(name, dep);
+    }
+
+    pub fn remove_dependency(&mut self, name: &str) -> bool {
+        match self.mox.dependencies.as_mut() {
+            Some(deps) => deps.remove(name).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn dependency(&self, name: &str) -> Option<&Dependency> {
+        self.mox.dependencies.as_ref()?.get(name)
+    }
+}
+
+impl NormalizedManifest {
+    /// The strongest checksum the package was published with
+    pub fn strongest_checksum(&self) -> &str {
+        self.checksum.as_deref().unwrap_or(&self.cksum)
+    }
+
+    /// What the publisher signs: the whole manifest, checksums and dependencies included,
+    /// as canonical JSON so registries may reformat the TOML but not change any field
+    pub fn signed_payload(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Whether `checksum` is any of the checksums the package was published with
+    pub fn has_checksum(&self, checksum: &str) -> bool {
+        self.cksum == checksum || self.checksum.as_deref() == Some(checksum)
+    }
+}
+
+impl External {
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Url(url) => url,
+            Self::Detailed(detail) => &detail.url,
+        }
+    }
+
+    /// Repository type, guessed from the URL like the CurseForge packager does when not given
+    pub fn kind(&self) -> &str {
+        if let Self::Detailed(ExternalDetail {
+            kind: Some(kind), ..
+        }) = self
+        {
+            return kind;
+        }
+
+        let url = self.url();
+        if url.starts_with("svn") || url.trim_end_matches('/').ends_with("/trunk") {
+            "svn"
         } else {
-            let deps = vec![dep];
-            self.mox.dependencies = Some(deps);
+            "git"
         }
     }
 }
 
+impl Dependency {
+    pub fn version(&self) -> &str {
+        match self {
+            Self::Version(version) => version,
+            Self::Detailed(detail) => detail.version.as_deref().unwrap_or("*"),
+        }
+    }
+
+    pub fn registry(&self) -> Option<&str> {
+        match self {
+            Self::Version(_) => None,
+            Self::Detailed(detail) => detail.registry.as_deref(),
+        }
+    }
+
+    pub fn version_req(&self) -> Result<VersionReq, MoxenError> {
+        VersionReq::parse(self.version()).map_err(|e| {
+            MoxenError::ConfigError(format!(
+                "invalid version requirement `{}` - {e}",
+                self.version()
+            ))
+        })
+    }
+}
+
+impl WowVersion {
+    /// Parses a comma-separated list of versions, as used for multi-interface TOCs
+    pub fn parse_list(versions: &str) -> Result<Vec<Self>, MoxenError> {
+        versions.split(',').map(str::parse).collect()
+    }
+
+    /// The `## Interface:` number of this version
+    pub fn interface(&self) -> u32 {
+        self.major * 10_000 + self.minor * 100 + self.patch
+    }
+
+    /// Formats `versions` as a comma-separated list
+    pub fn join(versions: &[Self], format: impl Fn(&Self) -> String) -> String {
+        versions.iter().map(format).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl FromStr for WowVersion {
+    type Err = MoxenError;
+
+    /// Accepts both `11.0.1` and the Interface number `110001`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid =
+            || MoxenError::ConfigError(format!("`{s}` is not a WoW version such as 11.0.1"));
+
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            let interface: u32 = s.parse().map_err(|_| invalid())?;
+            if !(10_000..10_000_000).contains(&interface) {
+                return Err(invalid());
+            }
+
+            return Ok(Self {
+                major: interface / 10_000,
+                minor: interface / 100 % 100,
+                patch: interface % 100,
+            });
+        }
+
+        let parts = s
+            .split('.')
+            .map(|part| part.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match parts[..] {
+            [major, minor, patch] if major > 0 && major < 1000 && minor < 100 && patch < 100 => {
+                Ok(Self {
+                    major,
+                    minor,
+                    patch,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for WowVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Splits a `name@requirement` dependency spec as given on the command line
+pub fn parse_dependency_spec(spec: &str) -> Result<(String, Option<VersionReq>), MoxenError> {
+    match spec.split_once('@') {
+        Some((name, req)) => {
+            validate_dependency_name(name)?;
+            let req = VersionReq::parse(req).map_err(|e| {
+                MoxenError::GeneralError(format!("invalid version requirement `{req}` - {e}"))
+            })?;
+            Ok((name.to_owned(), Some(req)))
+        }
+        None => {
+            validate_dependency_name(spec)?;
+            Ok((spec.to_owned(), None))
+        }
+    }
+}
+
+/// Checks that a dependency name is a single folder name, as it becomes `libs/<name>`
+pub fn validate_dependency_name(name: &str) -> Result<(), MoxenError> {
+    let folder = contained_path(name).is_some_and(|path| path.components().count() == 1);
+    if !folder || name.starts_with('.') || name.contains('\\') {
+        return Err(MoxenError::ConfigError(format!(
+            "`{name}` is not a valid package name"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Accepts both the old list form (`["ace3"]`) and the table form (`ace3 = "^3.2"`)
+fn deserialize_dependencies<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<String, Dependency>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawDependencies {
+        List(Vec<String>),
+        Table(BTreeMap<String, Dependency>),
+    }
+
+    let deps = match Option::<RawDependencies>::deserialize(deserializer)? {
+        Some(RawDependencies::List(names)) => Some(
+            names
+                .into_iter()
+                .map(|name| (name, Dependency::Version("*".to_string())))
+                .collect(),
+        ),
+        Some(RawDependencies::Table(table)) => Some(table),
+        None => None,
+    };
+
+    Ok(deps)
+}
+
+/// A package as published on a registry, alongside every version of it
+pub struct RegistryPackage {
+    pub manifest: NormalizedManifest,
+    pub versions: Vec<String>,
+}
+
 fn get_user_input(msg: &str) -> String {
     let mut buf = String::new();
     print!("{msg}: ");
@@ -241,6 +590,15 @@ impl fmt::Display for PackageManifest {
             writeln!(f, "Source Code: {repo}")?;
         }
 
+        if let Some(deps) = &addon.dependencies {
+            if !deps.is_empty() {
+                writeln!(f, "Dependencies:")?;
+            }
+            for (name, dep) in deps.iter() {
+                writeln!(f, "- {name} ({})", dep.version())?;
+            }
+        }
+
         writeln!(f, "------")?;
 
         Ok(())
@@ -299,30 +657,205 @@ pub fn bootstrap_gitignore(dir: impl AsRef<Path>) -> Result<()> {
 }
 
 pub fn bootstrap_toc(dir: impl AsRef<Path>, manifest: &PackageManifest) -> Result<()> {
-    let mox = &manifest.mox;
-    let mut normalised_name = mox.name.replace(" ", "");
-    normalised_name.push_str(".toc");
-    let toc_path = dir.as_ref().join(normalised_name);
-    let mut f = std::fs::File::create(toc_path)?;
-
-    f.write(
-        format!("## Interface: <Current World of Warcraft Version Here (e.g. 110001)>\n")
-            .as_bytes(),
-    )?;
-    let mox_version = if let Some(version) = &mox.version {
-        version
-    } else {
-        &"0.1.0".to_string()
+    let dir = dir.as_ref();
+    // A fresh manifest has no real wow_version yet, so the Interface is left to fill in
+    let interface = match manifest.wow_versions() {
+        Ok(versions) => toc::interface_line(&versions),
+        Err(_) => toc::INTERFACE_PLACEHOLDER.to_string(),
     };
-    f.write(format!("## Version: {mox_version}\n").as_bytes())?;
+    let contents = toc::generate_toc(dir, manifest, &interface, None, &[], "")?;
+    std::fs::write(toc::toc_path(dir, manifest), contents)?;
 
-    f.write(format!("## Title: {}\n", mox.name).as_bytes())?;
-    f.write("## Notes: Created with Moxen\n".as_bytes())?;
+    Ok(())
+}
 
-    let author = mox.authors.join(",");
-    f.write(format!("## Author: {author}\n").as_bytes())?;
-    f.write("\n".as_bytes())?;
-    f.write("start.lua\n".as_bytes())?;
+impl fmt::Display for RegistryPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pkg = &self.manifest;
 
-    Ok(())
+        writeln!(f, "--- Mox Package ---")?;
+        writeln!(f, "Name: {}", pkg.name)?;
+        if let Some(version) = &pkg.version {
+            writeln!(f, "Addon Version: {version}")?;
+        }
+        if !self.versions.is_empty() {
+            writeln!(f, "Published Versions: {}", self.versions.join(", "))?;
+        }
+        writeln!(f, "World of Warcraft Version: {}", pkg.wow_version)?;
+        writeln!(f, "Addon Description: \"{}\"", pkg.description)?;
+
+        if !pkg.authors.is_empty() {
+            writeln!(f, "Authors:")?;
+            for author in &pkg.authors {
+                writeln!(f, "- {author}")?;
+            }
+        }
+
+        if !pkg.categories.is_empty() {
+            writeln!(f, "Categories:")?;
+        }
+        for category in pkg.categories.iter() {
+            writeln!(f, "- {category}")?;
+        }
+
+        if let Some(homepage) = &pkg.homepage {
+            writeln!(f, "Home: {homepage}")?;
+        }
+
+        if let Some(repo) = &pkg.repository {
+            writeln!(f, "Source Code: {repo}")?;
+        }
+
+        if !pkg.dependencies.is_empty() {
+            writeln!(f, "Dependencies:")?;
+        }
+        for (name, req) in pkg.dependencies.iter() {
+            writeln!(f, "- {name} ({req})")?;
+        }
+
+        writeln!(f, "Checksum: {}", pkg.strongest_checksum())?;
+        writeln!(f, "------")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moxen::auth::{generate_keyfile_pair, verify_signature};
+
+    #[test]
+    fn parses_dotted_and_interface_versions() {
+        let version = WowVersion {
+            major: 11,
+            minor: 0,
+            patch: 1,
+        };
+        assert_eq!("11.0.1".parse::<WowVersion>().unwrap(), version);
+        assert_eq!("110001".parse::<WowVersion>().unwrap(), version);
+        assert_eq!(" 1.13.7 ".parse::<WowVersion>().unwrap().interface(), 11307);
+        assert_eq!(version.interface(), 110001);
+        assert_eq!(version.to_string(), "11.0.1");
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for invalid in ["", "11.0", "11.0.1.2", "0.1.0", "11.100.0", "999", "11.0.x"] {
+            assert!(invalid.parse::<WowVersion>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_version_lists() {
+        let versions = WowVersion::parse_list("11.0.2, 40400,1.15.3").unwrap();
+        assert_eq!(
+            versions
+                .iter()
+                .map(WowVersion::interface)
+                .collect::<Vec<_>>(),
+            vec![110002, 40400, 11503]
+        );
+        assert_eq!(
+            WowVersion::join(&versions, |v| v.interface().to_string()),
+            "110002, 40400, 11503"
+        );
+        assert!(WowVersion::parse_list("11.0.2,").is_err());
+    }
+
+    #[test]
+    fn refuses_the_placeholder_wow_version() {
+        let mut manifest = PackageManifest::fresh("My Addon");
+        assert!(matches!(
+            manifest.wow_versions(),
+            Err(MoxenError::ConfigError(_))
+        ));
+
+        manifest.mox.wow_version = "11.0.2".to_string();
+        assert_eq!(manifest.wow_versions().unwrap()[0].interface(), 110002);
+    }
+
+    #[test]
+    fn signatures_cover_the_whole_manifest() {
+        let keypair = generate_keyfile_pair(&mut None).unwrap();
+        let public_key = keypair.public_key_as_string();
+        let mut manifest: NormalizedManifest = toml::from_str(
+            "name = \"ace3\"\nversion = \"1.0.0\"\nwow_version = \"11.0.2\"\ncategories = []\ncksum = \"aaaa\"\n\n[dependencies]\nzlib = \"^1\"\n",
+        )
+        .unwrap();
+        let signature = keypair.sign_message(&manifest.signed_payload().unwrap());
+
+        // Reformatting the manifest keeps the signature valid
+        let reformatted: NormalizedManifest =
+            toml::from_str(&toml::to_string_pretty(&manifest).unwrap()).unwrap();
+        let payload = reformatted.signed_payload().unwrap();
+        assert!(verify_signature(&public_key, &payload, &signature).is_ok());
+
+        manifest
+            .dependencies
+            .insert("evil".to_string(), "*".to_string());
+        let payload = manifest.signed_payload().unwrap();
+        assert!(verify_signature(&public_key, &payload, &signature).is_err());
+
+        manifest.dependencies.remove("evil");
+        manifest.wow_version = "1.15.3".to_string();
+        let payload = manifest.signed_payload().unwrap();
+        assert!(verify_signature(&public_key, &payload, &signature).is_err());
+    }
+
+    #[test]
+    fn dependency_names_stay_inside_libs() {
+        for valid in ["ace3", "LibStub", "lib-sink-2.0"] {
+            assert!(validate_dependency_name(valid).is_ok(), "{valid}");
+        }
+        for invalid in ["", ".", "..", "../x", "a/b", "/abs", ".hidden", "a\\b"] {
+            assert!(validate_dependency_name(invalid).is_err(), "{invalid}");
+        }
+
+        assert!(parse_dependency_spec("../../x@^1").is_err());
+        let (name, req) = parse_dependency_spec("ace3@^3.2").unwrap();
+        assert_eq!(name, "ace3");
+        assert_eq!(req.unwrap().to_string(), "^3.2");
+    }
+
+    fn with_dependencies(dependencies: &str) -> PackageManifest {
+        toml::from_str(&format!(
+            "[mox]\nname = \"My Addon\"\ndescription = \"\"\nwow_version = \"11.0.2\"\nauthors = []\n{dependencies}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn loads_the_old_dependency_list() {
+        let manifest = with_dependencies("dependencies = [\"ace3\", \"LibStub\"]\n");
+        for name in ["ace3", "LibStub"] {
+            let dep = manifest.dependency(name).unwrap();
+            assert_eq!(dep.version(), "*");
+            assert_eq!(dep.version_req().unwrap(), VersionReq::STAR);
+        }
+    }
+
+    #[test]
+    fn loads_the_dependency_table() {
+        let manifest = with_dependencies(
+            "[mox.dependencies]\nace3 = \"^3.2\"\nzlib = { version = \"~1.1\", registry = \"guild\" }\nLibStub = {}\n",
+        );
+        assert_eq!(manifest.dependency("ace3").unwrap().version(), "^3.2");
+        let zlib = manifest.dependency("zlib").unwrap();
+        assert_eq!(zlib.version_req().unwrap().to_string(), "~1.1");
+        assert_eq!(zlib.registry(), Some("guild"));
+        assert_eq!(manifest.dependency("LibStub").unwrap().version(), "*");
+    }
+
+    #[test]
+    fn reports_invalid_requirements() {
+        let manifest = with_dependencies("[mox.dependencies]\nace3 = \"three or so\"\n");
+        assert!(matches!(
+            manifest.dependency("ace3").unwrap().version_req(),
+            Err(MoxenError::ConfigError(_))
+        ));
+
+        let invalid = "[mox]\nname = \"My Addon\"\ndescription = \"\"\nwow_version = \"11.0.2\"\nauthors = []\ndependencies = 3\n";
+        assert!(toml::from_str::<PackageManifest>(invalid).is_err());
+    }
 }
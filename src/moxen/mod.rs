@@ -1,34 +1,57 @@
 pub mod api;
 pub mod auth;
+pub mod check;
 pub mod config;
 pub mod download;
+pub mod lockfile;
 pub mod manifest;
 pub mod package;
+pub mod pkgmeta;
 pub mod publish;
+pub mod report;
+pub mod resolve;
+pub mod search;
+pub mod toc;
+pub mod tree;
 
 use anyhow::{Context, Result};
+use api::{Registry, SearchQuery};
 use config::MoxenConfig;
-use std::path::PathBuf;
-use tokio::sync::mpsc::channel;
+use lockfile::Lockfile;
+use semver::{Version, VersionReq};
+use std::{collections::BTreeMap, path::PathBuf};
 
 use crate::{
     common::{copy_directory, create_project_dir, MoxenError},
-    DocumentationType,
+    DocumentationType, TreeFormat,
+};
+use manifest::{
+    bootstrap_gitignore, bootstrap_lua, bootstrap_toc, parse_dependency_spec,
+    validate_dependency_name, Dependency, DependencyDetail, NormalizedManifest, PackageManifest,
+    RegistryPackage,
 };
-use manifest::{bootstrap_gitignore, bootstrap_lua, bootstrap_toc, PackageManifest};
 use package::package_content;
 use publish::publish_package;
+use report::{InstallReport, PackageStatus};
+use resolve::{PackageSource, Resolution, Resolver, Updates};
+use tree::DependencyGraph;
 
 #[allow(dead_code)]
 pub struct Manager {
     mox_dir: PathBuf,
     src_dir: PathBuf,
-    manifest: PackageManifest,
+    /// `None` outside of a project, for commands which don't need one
+    manifest: Option<PackageManifest>,
     config: MoxenConfig,
+    registry: Registry,
 }
 
 impl Manager {
-    pub fn new(target_dir: Option<String>) -> Result<Self, MoxenError> {
+    pub fn new(
+        target_dir: Option<String>,
+        registry: Option<String>,
+        needs_manifest: bool,
+    ) -> Result<Self, MoxenError> {
         let dir = if let Some(dir) = target_dir {
             PathBuf::from(dir)
                 .canonicalize()
@@ -42,29 +65,26 @@ impl Manager {
 
         let mox_dir = create_project_dir().map_err(|e| MoxenError::GeneralError(e.to_string()))?;
         let config = MoxenConfig::load(&mox_dir).map_err(|e| {
-            MoxenError::LoadError(format!(
-                "could not load Moxen config file - {}",
-                e.to_string()
-            ))
+            MoxenError::LoadError(format!("could not load Moxen config file - {}", e))
         })?;
+        let registry = config.registry(registry.as_deref())?;
         std::env::set_current_dir(&dir).map_err(|e| {
-            MoxenError::GeneralError(format!(
-                "could not set current directory - {}",
-                e.to_string()
-            ))
-        })?;
-        let manifest = PackageManifest::load(&dir).map_err(|e| {
-            MoxenError::LoadError(format!(
-                "could not load Moxen.toml manifest - {}",
-                e.to_string()
-            ))
+            MoxenError::GeneralError(format!("could not set current directory - {}", e))
         })?;
+        let manifest = if needs_manifest || dir.join("Moxen.toml").exists() {
+            Some(PackageManifest::load(&dir).map_err(|e| {
+                MoxenError::LoadError(format!("could not load Moxen.toml manifest - {}", e))
+            })?)
+        } else {
+            None
+        };
 
         Ok(Self {
             mox_dir,
             src_dir: dir,
             manifest,
             config,
+            registry,
         })
     }
 
@@ -92,41 +112,192 @@ impl Manager {
         Ok(())
     }
 
-    pub fn info(&self) {
-        println!("{}", self.manifest);
+    pub fn info(&self) -> Result<()> {
+        println!("{}", self.manifest()?);
+        Ok(())
+    }
+
+    pub async fn remote_info(&self, spec: &str) -> Result<()> {
+        let (name, req) = parse_dependency_spec(spec)?;
+        let versions = api::fetch_versions(&self.registry, &name).await?;
+        let version = match spec.split_once('@') {
+            // An exact version is looked up as is, anything else is a requirement
+            Some((_, version)) if Version::parse(version).is_ok() => Some(version.to_owned()),
+            _ => match req {
+                Some(req) => download::resolve_version(&self.registry, &name, &req).await?,
+                None => None,
+            },
+        };
+
+        let manifest = api::fetch_manifest(&self.registry, &name, version.as_deref()).await?;
+        let manifest = toml::from_str::<NormalizedManifest>(&manifest)?;
+        print!("{}", RegistryPackage { manifest, versions });
+
+        Ok(())
+    }
+
+    pub async fn search(&self, query: SearchQuery, json: bool) -> Result<()> {
+        let results = api::search(&self.registry, &query).await?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        } else if results.is_empty() {
+            println!("No packages found");
+        } else {
+            print!("{}", search::render_table(&results));
+        }
+
+        Ok(())
+    }
+
+    pub fn tree(&self, invert: Option<String>, format: TreeFormat) -> Result<()> {
+        let graph = DependencyGraph::load(&self.src_dir, self.manifest()?)?;
+        let output = match format {
+            TreeFormat::Text => graph.render_text(invert.as_deref())?,
+            TreeFormat::Dot => graph.render_dot(invert.as_deref())?,
+        };
+        print!("{output}");
+
+        Ok(())
     }
 
     pub fn package(&self) -> Result<PathBuf> {
+        let manifest = self.manifest()?;
+        if let Err(err) = manifest.wow_versions() {
+            eprintln!("Set `wow_version` in Moxen.toml to the WoW version(s) the addon supports");
+            anyhow::bail!(err);
+        }
+
         let ignore_list = self.generate_ignore_list();
-        return package_content(&self.manifest, &self.src_dir, &self.mox_dir, ignore_list);
+        // Hand-written TOCs are left alone, `moxen toc --force` converts them
+        let toc = toc::toc_path(&self.src_dir, manifest);
+        if !toc.exists() || toc::is_generated(&toc) {
+            let ignore = ignore_list.as_deref().unwrap_or_default();
+            toc::write_toc(&self.src_dir, manifest, ignore, false)?;
+        }
+        self.check_tocs(false)?;
+
+        package_content(manifest, &self.src_dir, &self.mox_dir, ignore_list)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        self.check_tocs(true)
+    }
+
+    /// Checks the TOC files, failing on problems which would stop the addon loading.
+    /// Warnings are only shown in `verbose` mode.
+    fn check_tocs(&self, verbose: bool) -> Result<()> {
+        let ignore_list = self.generate_ignore_list();
+        let ignore = ignore_list.as_deref().unwrap_or_default();
+        let report = check::check_project(&self.src_dir, self.manifest()?, ignore)?;
+        if verbose && report.is_empty() {
+            println!("No problems found");
+        } else if verbose || report.errors() > 0 {
+            print!("{report}");
+        }
+
+        if report.errors() > 0 {
+            anyhow::bail!(MoxenError::TocCheckFailure(report.errors()));
+        }
+
+        Ok(())
+    }
+
+    pub fn toc(&self, force: bool) -> Result<()> {
+        let ignore_list = self.generate_ignore_list();
+        let ignore = ignore_list.as_deref().unwrap_or_default();
+        for path in toc::write_toc(&self.src_dir, self.manifest()?, ignore, force)? {
+            println!("Generated {}", path.display());
+        }
+
+        Ok(())
     }
 
     pub async fn publish(self) -> Result<()> {
-        match self.config.credentials {
-            Some(ref credentials) => {
-                let pkg_path = self.package()?;
-                if let Some(api_key) = &credentials.api_key {
-                    publish_package(self.manifest, pkg_path, api_key, &credentials.username).await
-                } else {
-                    eprintln!(
-                        "No API Key present. You may need to re-register for another API Key"
-                    );
-                    return Err(MoxenError::GeneralError("missing api key".to_string()).into());
+        let (api_key, username) = self.api_credentials()?;
+        let keypair = self.signing_key()?;
+        let pkg_path = self.package()?;
+        let manifest = self.manifest.ok_or(MoxenError::MissingManifestFile)?;
+        publish_package(
+            &self.registry,
+            manifest,
+            pkg_path,
+            &keypair,
+            &api_key,
+            &username,
+        )
+        .await
+    }
+
+    /// Adds or removes an owner of `package` (this project by default), listing them otherwise
+    pub async fn owners(
+        &self,
+        package: Option<String>,
+        add: Option<String>,
+        remove: Option<String>,
+    ) -> Result<()> {
+        let name = match package {
+            Some(package) => package,
+            None => self.manifest()?.normalise_name(false),
+        };
+        match (add, remove) {
+            (Some(owner), _) => {
+                let (api_key, username) = self.api_credentials()?;
+                api::add_owner(&self.registry, &name, &owner, &api_key, &username).await?;
+                println!("Added {owner} as an owner of {name}");
+            }
+            (None, Some(owner)) => {
+                let (api_key, username) = self.api_credentials()?;
+                api::remove_owner(&self.registry, &name, &owner, &api_key, &username).await?;
+                println!("Removed {owner} as an owner of {name}");
+            }
+            (None, None) => {
+                let owners = api::owners(&self.registry, &name).await?;
+                println!("Owners of {name}:");
+                for owner in owners {
+                    println!("- {owner}");
                 }
             }
-            None => {
-                eprintln!("No saved credentials present. You must signup to the Moxen Registry!");
-                return Err(MoxenError::GeneralError("missing credentials".to_string()).into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn yank(&self, spec: &str, undo: bool) -> Result<()> {
+        let (name, version) = match spec.split_once('@') {
+            Some((name, version)) if Version::parse(version).is_ok() => (name, version),
+            _ => {
+                eprintln!("Expected an exact version to yank, e.g. {spec}@1.0.0");
+                anyhow::bail!(MoxenError::GeneralError(format!(
+                    "invalid yank target `{spec}`"
+                )));
             }
+        };
+
+        let (api_key, username) = self.api_credentials()?;
+        api::yank(&self.registry, name, version, !undo, &api_key, &username).await?;
+        if undo {
+            println!("Restored {name} v{version}");
+        } else {
+            println!("Yanked {name} v{version}");
         }
+
+        Ok(())
     }
 
     // TODO: Improve name and capabilities
     pub fn convert_to_mox(&self) -> Result<()> {
         match self.src_dir.file_name() {
             Some(dir) => {
-                let name = dir.to_str().unwrap_or("Moxen Package");
-                let manifest = PackageManifest::interactive(name);
+                let pkgmeta = pkgmeta::PkgMeta::load(&self.src_dir)?;
+                let name = pkgmeta
+                    .as_ref()
+                    .and_then(|pkgmeta| pkgmeta.package_as.clone())
+                    .unwrap_or_else(|| dir.to_str().unwrap_or("Moxen Package").to_string());
+                let mut manifest = PackageManifest::interactive(&name);
+                if let Some(pkgmeta) = pkgmeta {
+                    pkgmeta.apply(&mut manifest)?;
+                    println!("Imported packaging settings from .pkgmeta");
+                }
                 manifest.write(&self.src_dir)?;
                 println!("Bootstrapped new mox: {name}");
             }
@@ -142,27 +313,225 @@ impl Manager {
         Ok(())
     }
 
-    pub async fn download_dependencies(&mut self, deps: Vec<String>) -> Result<()> {
-        let size = deps.len();
-        let (tx, mut rx) = channel(size);
-        for dep in deps.into_iter() {
-            let src_dir = self.src_dir.clone();
-            let sender = tx.clone();
-            tokio::task::spawn(async move {
-                if let Ok(_) = download::download_dependency(src_dir, &dep).await {
-                    if let Err(_) = sender.send(dep).await {
-                        eprintln!("dep receiver dropped");
-                    }
+    pub async fn download_dependencies(
+        &mut self,
+        deps: Vec<String>,
+        locked: bool,
+        all_or_nothing: bool,
+        require_signatures: bool,
+    ) -> Result<()> {
+        // No names means install everything the manifest already declares
+        let deps = if deps.is_empty() {
+            self.manifest()?
+                .mox
+                .dependencies
+                .as_ref()
+                .map(|deps| deps.keys().cloned().collect())
+                .unwrap_or_default()
+        } else {
+            deps
+        };
+
+        let mut roots = Vec::with_capacity(deps.len());
+        let mut requested = Vec::with_capacity(deps.len());
+        for spec in deps.into_iter() {
+            let (dep, req) = parse_dependency_spec(&spec)?;
+            let resolve_req = match &req {
+                Some(req) => req.clone(),
+                None => match self.manifest()?.dependency(&dep) {
+                    Some(existing) => existing.version_req()?,
+                    None => VersionReq::STAR,
+                },
+            };
+            let registry = self.dependency_registry(&dep)?;
+            roots.push((dep.clone(), resolve_req, registry));
+            requested.push((dep, req));
+        }
+
+        let mut lockfile = Lockfile::load(&self.src_dir)?;
+        let resolver = Resolver::new(
+            &self.src_dir,
+            self.manifest()?.normalise_name(false),
+            &lockfile,
+            locked,
+        )
+        .requiring_signatures(require_signatures || self.config.require_signatures);
+        let resolution = resolver
+            .resolve(roots)
+            .await
+            .context("resolving dependencies")?;
+
+        if locked && unpinned(&resolution, &lockfile) {
+            anyhow::bail!(MoxenError::LockfileOutdated(
+                "resolved packages differ from Moxen.lock".to_string()
+            ));
+        }
+
+        let resolved_versions: BTreeMap<String, Option<String>> = resolution
+            .packages
+            .iter()
+            .map(|(name, pkg)| (name.clone(), pkg.version.clone()))
+            .collect();
+
+        let mut report = InstallReport::default();
+        let lock_changed = self.install_resolution(
+            resolution,
+            &BTreeMap::new(),
+            &mut lockfile,
+            &mut report,
+            all_or_nothing,
+        )?;
+        let failures = report.failures();
+        if all_or_nothing && failures > 0 {
+            print!("{report}");
+            eprintln!("Nothing was installed as --all-or-nothing was passed");
+            anyhow::bail!(MoxenError::InstallFailure(failures));
+        }
+
+        for (dep, req) in requested.into_iter() {
+            if !report.succeeded(&dep) {
+                continue;
+            }
+
+            // Keep any requirement already in the manifest unless a new one was asked for
+            let version = match req {
+                Some(req) => req.to_string(),
+                None if self.manifest()?.dependency(&dep).is_some() => continue,
+                None => resolved_versions
+                    .get(&dep)
+                    .cloned()
+                    .flatten()
+                    .map(|version| format!("^{version}"))
+                    .unwrap_or_else(|| "*".to_string()),
+            };
+            // Only spell out the registry when it isn't the one used by default
+            let dep_spec = if self.registry == self.config.registry(None)? {
+                Dependency::Version(version)
+            } else {
+                Dependency::Detailed(DependencyDetail {
+                    version: Some(version),
+                    registry: Some(self.registry.name.clone()),
+                })
+            };
+            self.manifest_mut()?.add_dependency(dep, dep_spec);
+        }
+
+        self.manifest()?.write(&self.src_dir)?;
+        // With --locked the only change left is upgrading legacy SHA-1 pins, which waits
+        // for an unlocked run
+        if lock_changed && !locked {
+            lockfile.write(&self.src_dir)?;
+        }
+
+        print!("{report}");
+        if failures > 0 {
+            anyhow::bail!(MoxenError::InstallFailure(failures));
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_dependencies(
+        &mut self,
+        names: Vec<String>,
+        require_signatures: bool,
+    ) -> Result<()> {
+        let installed = download::installed_packages(&self.src_dir)?;
+        let updates = if names.is_empty() {
+            Updates::All
+        } else {
+            for name in names.iter() {
+                if self.manifest()?.dependency(name).is_none() && !installed.contains(name) {
+                    eprintln!("{name} is not a dependency of this project");
+                    anyhow::bail!(MoxenError::ProjectNotFound(name.to_owned()));
                 }
-            });
+            }
+            Updates::Only(names.into_iter().collect())
+        };
+
+        let previous: BTreeMap<String, Option<String>> = installed
+            .into_iter()
+            .map(|name| {
+                let version = download::installed_manifest(&self.src_dir, &name)
+                    .and_then(|manifest| manifest.mox.version);
+                (name, version)
+            })
+            .collect();
+
+        let mut roots = vec![];
+        if let Some(deps) = &self.manifest()?.mox.dependencies {
+            for (name, dep) in deps.iter() {
+                let registry = self.dependency_registry(name)?;
+                roots.push((name.clone(), dep.version_req()?, registry));
+            }
+        }
+
+        let mut lockfile = Lockfile::load(&self.src_dir)?;
+        let resolver = Resolver::new(
+            &self.src_dir,
+            self.manifest()?.normalise_name(false),
+            &lockfile,
+            false,
+        )
+        .updating(updates)
+        .requiring_signatures(require_signatures || self.config.require_signatures);
+        let resolution = resolver
+            .resolve(roots)
+            .await
+            .context("resolving dependencies")?;
+
+        let mut report = InstallReport::default();
+        let lock_changed =
+            self.install_resolution(resolution, &previous, &mut lockfile, &mut report, false)?;
+        if lock_changed {
+            lockfile.write(&self.src_dir)?;
         }
-        drop(tx);
 
-        while let Some(dep) = rx.recv().await {
-            self.manifest.add_dependency(dep.to_owned());
+        print!("{report}");
+        let failures = report.failures();
+        if failures > 0 {
+            anyhow::bail!(MoxenError::InstallFailure(failures));
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_dependencies(&mut self, deps: Vec<String>) -> Result<()> {
+        let mut lockfile = Lockfile::load(&self.src_dir)?;
+        let mut lock_changed = false;
+        for dep in deps.iter() {
+            validate_dependency_name(dep)?;
+            let in_manifest = self.manifest_mut()?.remove_dependency(dep);
+            let libs_dir = download::libs_dir(&self.src_dir, dep);
+            let installed = libs_dir.exists();
+            if !in_manifest && !installed {
+                eprintln!("{dep} is not a dependency of this project");
+                continue;
+            }
+
+            if installed {
+                std::fs::remove_dir_all(&libs_dir)
+                    .with_context(|| format!("removing {}", libs_dir.display()))?;
+            }
+            lock_changed |= lockfile.unlock(dep);
+            if download::unembed_dependency(&self.src_dir, dep)? {
+                println!("Removed {dep} from embeds.xml");
+            }
+            println!("Removed {dep}");
+
+            let dependents = download::installed_dependents(&self.src_dir, dep)?;
+            if !dependents.is_empty() {
+                eprintln!(
+                    "Warning: {dep} is still required by {}",
+                    dependents.join(", ")
+                );
+            }
         }
 
-        self.manifest.write(&self.src_dir)?;
+        self.manifest()?.write(&self.src_dir)?;
+        if lock_changed {
+            lockfile.write(&self.src_dir)?;
+        }
 
         Ok(())
     }
@@ -170,12 +539,13 @@ impl Manager {
     pub async fn register(&mut self, name: String) -> Result<()> {
         println!("Registering to Moxen Register as {name}...\n");
         auth::validate_username(&name)?;
-        let keypair = auth::generate_keyfile_pair(&mut self.config)?;
+        let keypair = auth::generate_keyfile_pair(self.config.credentials_mut(&self.registry))?;
         let public_key = keypair.public_key_as_string();
-        let challenge_string = api::generate_challenge(&name, &public_key).await?;
+        let challenge_string = api::generate_challenge(&self.registry, &name, &public_key).await?;
         let signed_challenge = keypair.sign_message(&challenge_string);
-        let (api_key, recovery_codes) = api::signup(challenge_string, signed_challenge).await?;
-        match &mut self.config.credentials {
+        let (api_key, recovery_codes) =
+            api::signup(&self.registry, challenge_string, signed_challenge).await?;
+        match self.config.credentials_mut(&self.registry) {
             Some(creds) => {
                 creds.api_key = Some(api_key.clone());
                 creds.username = name.clone();
@@ -201,16 +571,16 @@ impl Manager {
 
     pub async fn recover(&mut self, name: String, recovery_code: String) -> Result<()> {
         println!("Recovering user account for {name}...");
-        let keypair = auth::generate_keyfile_pair(&mut self.config)?;
+        let keypair = auth::generate_keyfile_pair(self.config.credentials_mut(&self.registry))?;
         let pub_key = keypair.public_key_as_string();
 
-        let challenge = api::generate_challenge(&name, &pub_key).await?;
+        let challenge = api::generate_challenge(&self.registry, &name, &pub_key).await?;
         let signed = keypair.sign_message(&challenge);
 
-        let api_key = api::recover(challenge, signed, recovery_code).await?;
+        let api_key = api::recover(&self.registry, challenge, signed, recovery_code).await?;
 
         println!("Successfully recovered user account!\nNew API Key: {api_key}");
-        if let Some(creds) = &mut self.config.credentials {
+        if let Some(creds) = self.config.credentials_mut(&self.registry) {
             creds.username = name;
             creds.api_key = Some(api_key);
             self.config.write()?;
@@ -263,13 +633,160 @@ impl Manager {
         Ok(())
     }
 
+    fn manifest(&self) -> Result<&PackageManifest, MoxenError> {
+        match &self.manifest {
+            Some(manifest) => Ok(manifest),
+            None => Err(missing_manifest(&self.src_dir)),
+        }
+    }
+
+    fn manifest_mut(&mut self) -> Result<&mut PackageManifest, MoxenError> {
+        match &mut self.manifest {
+            Some(manifest) => Ok(manifest),
+            None => Err(missing_manifest(&self.src_dir)),
+        }
+    }
+
+    /// API key and username saved for the current registry
+    fn api_credentials(&self) -> Result<(String, String)> {
+        let Some(credentials) = self.config.credentials(&self.registry) else {
+            eprintln!(
+                "No saved credentials present for the `{}` registry. You must signup to it first!",
+                self.registry.name
+            );
+            anyhow::bail!(MoxenError::GeneralError("missing credentials".to_string()));
+        };
+
+        match &credentials.api_key {
+            Some(api_key) => Ok((api_key.clone(), credentials.username.clone())),
+            None => {
+                eprintln!("No API Key present. You may need to re-register for another API Key");
+                anyhow::bail!(MoxenError::GeneralError("missing api key".to_string()))
+            }
+        }
+    }
+
+    /// Key used to sign packages published to the current registry
+    fn signing_key(&self) -> Result<auth::MoxenKeyPair> {
+        match self.config.credentials(&self.registry) {
+            Some(credentials) => Ok(auth::MoxenKeyPair::from_private_key(
+                &credentials.private_key,
+            )?),
+            None => anyhow::bail!(MoxenError::GeneralError("missing credentials".to_string())),
+        }
+    }
+
+    /// Registry a dependency is installed from, honouring a `registry` key in Moxen.toml
+    fn dependency_registry(&self, dep: &str) -> Result<Registry, MoxenError> {
+        match self.manifest()?.dependency(dep).and_then(|d| d.registry()) {
+            Some(name) => self.config.registry(Some(name)),
+            None => Ok(self.registry.clone()),
+        }
+    }
+
+    /// Installs everything fetched in `resolution`, recording the outcome of every
+    /// package in `report` and pinning whatever was installed in `lockfile`.
+    ///
+    /// Packages are all staged before any of them is moved into `libs/`, and with
+    /// `all_or_nothing` a package failing to move in puts back the ones moved before
+    /// it, so a single failure leaves the project untouched.
+    fn install_resolution(
+        &mut self,
+        resolution: Resolution,
+        previous: &BTreeMap<String, Option<String>>,
+        lockfile: &mut Lockfile,
+        report: &mut InstallReport,
+        all_or_nothing: bool,
+    ) -> Result<bool> {
+        for (name, err) in resolution.failures.iter() {
+            report.record_error(name, err);
+        }
+
+        let mut lock_changed = false;
+        let mut staged = vec![];
+        for (name, pkg) in resolution.packages.into_iter() {
+            match pkg.source {
+                PackageSource::Installed(manifest) => {
+                    if let Some(manifest) = manifest {
+                        lock_changed |= lockfile.lock(&name, &manifest);
+                    }
+                    report.record(&name, PackageStatus::AlreadyPresent(pkg.version))
+                }
+                PackageSource::Registry {
+                    manifest,
+                    package,
+                    publisher,
+                } => {
+                    let trusted = publisher.map(|publisher| (pkg.registry, publisher));
+                    match download::stage_dependency(&self.src_dir, &name, &package) {
+                        Ok(dir) => staged.push((name, manifest, dir, trusted)),
+                        Err(err) => report.record_error(&name, &err),
+                    }
+                }
+            }
+        }
+
+        if all_or_nothing && report.failures() > 0 {
+            for (_, _, dir, _) in staged.iter() {
+                download::discard_staged(dir)?;
+            }
+            return Ok(false);
+        }
+
+        let mut committed: Vec<(String, _, _)> = vec![];
+        let mut staged = staged.into_iter();
+        while let Some((name, manifest, dir, trusted)) = staged.next() {
+            if let Err(err) = download::commit_dependency(&self.src_dir, &name, &dir) {
+                download::discard_staged(&dir)?;
+                report.record_error(&name, &err);
+                if all_or_nothing {
+                    for (_, _, dir, _) in staged {
+                        download::discard_staged(&dir)?;
+                    }
+                    for (name, _, _) in committed.iter().rev() {
+                        download::rollback_dependency(&self.src_dir, name)?;
+                    }
+                    return Ok(false);
+                }
+                continue;
+            }
+            committed.push((name, manifest, trusted));
+        }
+
+        let mut config_changed = false;
+        for (name, manifest, trusted) in committed.into_iter() {
+            download::confirm_dependency(&self.src_dir, &name, &manifest)?;
+            let status = match previous.get(&name) {
+                Some(old) => PackageStatus::Updated((old.clone(), manifest.version.clone())),
+                None => PackageStatus::Installed(manifest.version.clone()),
+            };
+            report.record(&name, status);
+            lock_changed |= lockfile.lock(&name, &manifest);
+            if let Some((registry, publisher)) = trusted {
+                if self.config.trust_publisher(&registry, &publisher) {
+                    println!(
+                        "Pinned the signing key of {} on the `{}` registry",
+                        publisher.publisher, registry.name
+                    );
+                    config_changed = true;
+                }
+            }
+        }
+
+        if config_changed {
+            self.config.write()?;
+        }
+
+        Ok(lock_changed)
+    }
+
     fn generate_ignore_list(&self) -> Option<Vec<PathBuf>> {
         let mut inner = vec![];
 
-        match &self.manifest.mox.ignore {
+        match self.manifest.as_ref().and_then(|m| m.mox.ignore.as_ref()) {
             Some(items) => {
                 let globs: Vec<String> = items
-                    .into_iter()
+                    .iter()
                     .map(|item| {
                         self.src_dir
                             .join(item)
@@ -282,16 +799,148 @@ impl Manager {
                 for pattern in globs.into_iter() {
                     let entries = glob::glob(&pattern)
                         .unwrap()
-                        .into_iter()
                         .map(|c| c.unwrap())
                         .collect::<Vec<PathBuf>>();
 
                     inner.extend(entries);
                 }
 
-                return Some(inner);
+                Some(inner)
             }
             None => None,
         }
     }
 }
+
+fn missing_manifest(dir: &std::path::Path) -> MoxenError {
+    eprintln!("No Moxen.toml file found in {}", dir.display());
+    MoxenError::MissingManifestFile
+}
+
+/// Whether any freshly fetched or newly pinned package is missing from Moxen.lock
+fn unpinned(resolution: &Resolution, lockfile: &Lockfile) -> bool {
+    resolution
+        .packages
+        .iter()
+        .any(|(name, pkg)| match &pkg.source {
+            PackageSource::Registry { manifest, .. } => !lockfile.pins(name, manifest),
+            PackageSource::Installed(Some(manifest)) => !lockfile.pins(name, manifest),
+            PackageSource::Installed(None) => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tarball;
+    use api::transfer::PackageWriter;
+    use config::NetworkConfig;
+    use lockfile::LockedPackage;
+    use resolve::ResolvedPackage;
+    use std::{io::Write, path::Path};
+
+    fn registry() -> Registry {
+        Registry {
+            name: "test".to_string(),
+            url: "file:///nonexistent".to_string(),
+            ca_bundle: None,
+            accept_invalid_certs: false,
+            network: NetworkConfig::default(),
+            publisher_keys: BTreeMap::new(),
+        }
+    }
+
+    /// A package of `name` as fetched from the registry, holding a single `version.txt`
+    fn fetched(name: &str, version: &str) -> ResolvedPackage {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("version.txt"), version).unwrap();
+        let mox = dir.path().join("pkg.mox");
+        tarball(&src, &mox, 0).unwrap();
+
+        let mut writer = PackageWriter::new().unwrap();
+        writer.write_all(&std::fs::read(&mox).unwrap()).unwrap();
+        let package = writer.finish().unwrap();
+        let manifest: NormalizedManifest = toml::from_str(&format!(
+            "name = \"{name}\"\nversion = \"{version}\"\nwow_version = \"11.0.2\"\ncategories = []\ncksum = \"{}\"\nchecksum = \"{}\"\n",
+            package.checksums.sha1, package.checksums.sha256
+        ))
+        .unwrap();
+
+        ResolvedPackage {
+            version: Some(version.to_string()),
+            dependencies: BTreeMap::new(),
+            path: vec!["app".to_string()],
+            req: VersionReq::STAR,
+            registry: registry(),
+            source: PackageSource::Registry {
+                manifest: Box::new(manifest),
+                package,
+                publisher: None,
+            },
+        }
+    }
+
+    fn installed_version(src_dir: &Path, name: &str) -> Option<String> {
+        std::fs::read_to_string(download::libs_dir(src_dir, name).join("version.txt")).ok()
+    }
+
+    #[test]
+    fn all_or_nothing_rolls_back_committed_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let src_dir = dir.path().to_path_buf();
+        let mut manager = Manager {
+            mox_dir: src_dir.join(".mox"),
+            src_dir: src_dir.clone(),
+            manifest: Some(PackageManifest::fresh("app")),
+            config: toml::from_str("").unwrap(),
+            registry: registry(),
+        };
+
+        // v1.0.0 of `a` is vendored and pinned, and a file in the way of moving the
+        // current `b` aside makes committing `b` fail after `a` went in
+        let libs_a = download::libs_dir(&src_dir, "a");
+        std::fs::create_dir_all(&libs_a).unwrap();
+        std::fs::write(libs_a.join("version.txt"), "1.0.0").unwrap();
+        std::fs::create_dir_all(download::libs_dir(&src_dir, "b")).unwrap();
+        std::fs::write(src_dir.join("libs").join(".b.old"), "").unwrap();
+        let pinned = vec![LockedPackage {
+            name: "a".to_string(),
+            version: Some("1.0.0".to_string()),
+            cksum: "sha256:aaaa".to_string(),
+        }];
+        let mut lockfile = Lockfile {
+            packages: pinned.clone(),
+        };
+
+        let resolution = Resolution {
+            packages: BTreeMap::from([
+                ("a".to_string(), fetched("a", "2.0.0")),
+                ("b".to_string(), fetched("b", "2.0.0")),
+            ]),
+            failures: BTreeMap::new(),
+        };
+        let mut report = InstallReport::default();
+        let lock_changed = manager
+            .install_resolution(
+                resolution,
+                &BTreeMap::new(),
+                &mut lockfile,
+                &mut report,
+                true,
+            )
+            .unwrap();
+
+        assert!(!lock_changed);
+        assert_eq!(lockfile.packages, pinned);
+        assert_eq!(report.failures(), 1);
+        assert_eq!(installed_version(&src_dir, "a").as_deref(), Some("1.0.0"));
+        assert_eq!(
+            download::installed_packages(&src_dir).unwrap(),
+            vec!["a", "b"]
+        );
+        assert!(!src_dir.join("libs").join(".a.old").exists());
+        assert!(!src_dir.join("libs").join(".b.partial").exists());
+    }
+}
@@ -1,22 +1,28 @@
-use crate::common::{gather_files, tarball, MoxenError};
+use crate::common::{contained_path, copy_directory, gather_files, tarball, MoxenError};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use super::manifest::PackageManifest;
+use super::manifest::{External, ExternalDetail, PackageManifest};
+
+/// Extensions of the files `@no-lib-strip@` blocks are removed from in a `-nolib` package
+static NOLIB_STRIPPED: [&str; 3] = ["toc", "xml", "lua"];
 
 pub fn package_content(
     manifest: &PackageManifest,
-    src_path: &PathBuf,
-    mox_path: &PathBuf,
+    src_path: &Path,
+    mox_path: &Path,
     ignore_list: Option<Vec<PathBuf>>,
 ) -> Result<PathBuf> {
     let name = manifest.normalise_name(true);
     println!("Packaging {} as {}...", src_path.display(), name);
     let package_target_path = mox_path.join("package").join(&name);
-    let compressed_target_path = mox_path.join("package").join(&format!("{name}.mox"));
-    if !check_for_toc(&src_path) {
+    let compressed_target_path = mox_path.join("package").join(format!("{name}.mox"));
+    if !check_for_toc(src_path) {
         eprintln!("No TOC file present, searching subdirectories...");
-        if !find_any_toc(&src_path) {
+        if !find_any_toc(src_path) {
             eprintln!(
                 "Cannot find a TOC file in the project: {}!",
                 src_path.display()
@@ -25,63 +31,101 @@ pub fn package_content(
         }
     }
 
-    package_mox(
-        src_path,
-        &package_target_path,
-        &compressed_target_path,
-        ignore_list,
-    )?;
-    println!("Crafted {}!", compressed_target_path.display());
+    let mtime = source_date_epoch(src_path);
+    stage_package(src_path, &package_target_path, ignore_list)?;
+    if let Some(externals) = &manifest.mox.externals {
+        fetch_externals(externals, &package_target_path)?;
+    }
+
+    // Moved folders become addons, and so packages, of their own
+    for (folder, addon) in manifest.mox.move_folders.iter().flatten() {
+        let folder = package_subpath(&package_target_path, "move_folders", folder)?;
+        if contained_path(addon).is_none_or(|addon| addon.components().count() != 1) {
+            eprintln!("`{addon}` in move_folders must be the name of an addon folder");
+            anyhow::bail!(MoxenError::ConfigError(format!(
+                "invalid move_folders entry `{addon}`"
+            )));
+        }
+        let moved = manifest.normalise_name_as(addon, true);
+        let moved_path = mox_path.join("package").join(&moved);
+        move_folder(&folder, &moved_path, &package_target_path)?;
+        craft(
+            &moved_path,
+            &mox_path.join("package").join(format!("{moved}.mox")),
+            mtime,
+        )?;
+    }
+
+    if manifest.mox.nolib {
+        let nolib_path = mox_path.join("package").join(format!("{name}-nolib"));
+        stage_nolib(
+            &package_target_path,
+            &nolib_path,
+            manifest.mox.externals.as_ref(),
+        )?;
+        craft(
+            &nolib_path,
+            &mox_path.join("package").join(format!("{name}-nolib.mox")),
+            mtime,
+        )?;
+    }
+
+    craft(&package_target_path, &compressed_target_path, mtime)?;
     Ok(compressed_target_path)
 }
 
-fn package_mox(
-    src_path: &PathBuf,
-    dst_path: &PathBuf,
-    compressed_dst_path: &PathBuf,
+fn craft(package_path: &Path, compressed_path: &Path, mtime: u64) -> Result<()> {
+    tarball(package_path, compressed_path, mtime)?;
+    println!("Crafted {}!", compressed_path.display());
+    Ok(())
+}
+
+fn stage_package(
+    src_path: &Path,
+    dst_path: &Path,
     ignore_list: Option<Vec<PathBuf>>,
 ) -> Result<()> {
-    let mut files = gather_files(&src_path)?;
+    let mut files = gather_files(src_path)?;
     if let Some(ignore) = ignore_list {
+        // An ignored directory takes everything below it along
         files = files
             .into_iter()
             .filter_map(|f| {
-                if ignore.contains(&f) {
+                if ignore.iter().any(|ignored| f.starts_with(ignored)) {
                     return None;
                 }
                 Some(f)
             })
             .collect();
     }
-    create_tarball(&src_path, files, dst_path, compressed_dst_path)?;
+    stage_files(src_path, files, dst_path)?;
     Ok(())
 }
 
-fn check_for_toc(cwd: &PathBuf) -> bool {
+fn check_for_toc(cwd: &Path) -> bool {
     let toc = glob::glob(cwd.join("*.toc").to_str().unwrap())
         .unwrap()
-        .into_iter()
         .count();
 
     toc != 0
 }
 
 // TODO: This could be better
-fn find_any_toc(cwd: &PathBuf) -> bool {
+fn find_any_toc(cwd: &Path) -> bool {
     let tocs = glob::glob(cwd.join("**/*.toc").to_str().unwrap())
         .unwrap()
-        .into_iter()
         .count();
 
     tocs != 0
 }
 
-fn create_tarball(
-    prefix: &PathBuf,
-    files: Vec<PathBuf>,
-    package_target_path: &PathBuf,
-    compressed_target_path: &PathBuf,
-) -> Result<()> {
+fn stage_files(prefix: &Path, files: Vec<PathBuf>, package_target_path: &Path) -> Result<()> {
+    // Leftovers from an earlier packaging run must not end up in this one
+    if package_target_path.exists() {
+        std::fs::remove_dir_all(package_target_path)?;
+    }
+    std::fs::create_dir_all(package_target_path)?;
+
     for file in files.into_iter() {
         let stripped = file.strip_prefix(prefix)?;
         if let Some(parent) = stripped.parent() {
@@ -92,7 +136,231 @@ fn create_tarball(
         std::fs::copy(&file, dst)?;
     }
 
-    tarball(package_target_path, &compressed_target_path)?;
+    Ok(())
+}
+
+/// Clones every external into the staged package, without its `.git` directory
+fn fetch_externals(externals: &BTreeMap<String, External>, package_path: &Path) -> Result<()> {
+    for (path, external) in externals.iter() {
+        if external.kind() != "git" {
+            eprintln!(
+                "External `{path}` is hosted on {}, only git externals can be fetched",
+                external.kind()
+            );
+            anyhow::bail!(MoxenError::ConfigError(format!(
+                "unsupported external `{path}`"
+            )));
+        }
+
+        let target = package_subpath(package_path, "externals", path)?;
+        println!("Fetching external {path} from {}...", external.url());
+        if target.exists() {
+            std::fs::remove_dir_all(&target)?;
+        }
+
+        let repo = git2::Repository::clone(external.url(), &target)?;
+        if let External::Detailed(detail) = external {
+            checkout_external(&repo, detail)?;
+        }
+        drop(repo);
+        std::fs::remove_dir_all(target.join(".git"))?;
+    }
+
+    Ok(())
+}
+
+/// Checks out the commit, tag or branch an external is pinned to
+fn checkout_external(repo: &git2::Repository, detail: &ExternalDetail) -> Result<()> {
+    let revision = match (&detail.commit, &detail.tag, &detail.branch) {
+        (Some(commit), _, _) => commit.clone(),
+        (None, Some(tag), _) if tag == "latest" => match latest_tag(repo)? {
+            Some(tag) => format!("refs/tags/{tag}"),
+            None => return Ok(()),
+        },
+        (None, Some(tag), _) => format!("refs/tags/{tag}"),
+        (None, None, Some(branch)) => format!("refs/remotes/origin/{branch}"),
+        (None, None, None) => return Ok(()),
+    };
+
+    let commit = repo.revparse_single(&revision)?.peel_to_commit()?;
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+
+    Ok(())
+}
+
+/// The tag pointing at the most recent commit
+fn latest_tag(repo: &git2::Repository) -> Result<Option<String>> {
+    let mut latest = None;
+    for tag in repo.tag_names(None)?.iter().flatten() {
+        let commit = repo
+            .revparse_single(&format!("refs/tags/{tag}"))?
+            .peel_to_commit()?;
+        let time = commit.time().seconds();
+        // Tag names come sorted, so on a tie the later name wins
+        if latest
+            .as_ref()
+            .is_none_or(|(_, latest_time)| time >= *latest_time)
+        {
+            latest = Some((tag.to_string(), time));
+        }
+    }
+
+    Ok(latest.map(|(tag, _)| tag))
+}
+
+/// Joins a path from Moxen.toml onto `root`, refusing any path which could leave it
+fn package_subpath(root: &Path, setting: &str, path: &str) -> Result<PathBuf> {
+    match contained_path(path) {
+        Some(relative) => Ok(root.join(relative)),
+        None => {
+            eprintln!("`{path}` in {setting} must be a relative path inside the package");
+            anyhow::bail!(MoxenError::ConfigError(format!(
+                "invalid {setting} entry `{path}`"
+            )));
+        }
+    }
+}
+
+/// Moves `folder` out of the staged package in `package_path` into a staged package
+/// of its own
+fn move_folder(folder: &Path, moved_path: &Path, package_path: &Path) -> Result<()> {
+    if !folder.is_dir() {
+        eprintln!(
+            "Cannot move {}, it is not a folder in the package",
+            folder.display()
+        );
+        anyhow::bail!(MoxenError::ConfigError(
+            "invalid move_folders entry".to_string()
+        ));
+    }
+
+    if moved_path.exists() {
+        std::fs::remove_dir_all(moved_path)?;
+    }
+    copy_directory(folder, moved_path)?;
+    std::fs::remove_dir_all(folder)?;
+    remove_empty_parents(folder, package_path)?;
+
+    Ok(())
+}
+
+/// Removes the directories left empty above a removed `path`, stopping short of `root`
+fn remove_empty_parents(path: &Path, root: &Path) -> Result<()> {
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == root || !dir.starts_with(root) || std::fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        std::fs::remove_dir(dir)?;
+        parent = dir.parent();
+    }
 
     Ok(())
 }
+
+/// Copies the staged package without its externals, dropping `@no-lib-strip@` blocks
+fn stage_nolib(
+    package_path: &Path,
+    nolib_path: &Path,
+    externals: Option<&BTreeMap<String, External>>,
+) -> Result<()> {
+    if nolib_path.exists() {
+        std::fs::remove_dir_all(nolib_path)?;
+    }
+    copy_directory(package_path, nolib_path)?;
+
+    for path in externals.into_iter().flat_map(|externals| externals.keys()) {
+        let external = package_subpath(nolib_path, "externals", path)?;
+        if external.exists() {
+            std::fs::remove_dir_all(&external)?;
+            remove_empty_parents(&external, nolib_path)?;
+        }
+    }
+
+    for file in gather_files(nolib_path)? {
+        let strip = file
+            .extension()
+            .is_some_and(|ext| NOLIB_STRIPPED.contains(&ext.to_string_lossy().as_ref()));
+        if strip {
+            // Locale files are often Latin-1, so strip on bytes rather than text
+            let contents = std::fs::read(&file)?;
+            std::fs::write(&file, strip_nolib_blocks(&contents))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes every line from `@no-lib-strip@` up to and including `@end-no-lib-strip@`
+fn strip_nolib_blocks(contents: &[u8]) -> Vec<u8> {
+    let mut stripping = false;
+    let mut kept = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|&byte| byte == b'\n') {
+        if contains(line, b"@end-no-lib-strip@") {
+            stripping = false;
+        } else if contains(line, b"@no-lib-strip@") {
+            stripping = true;
+        } else if !stripping {
+            kept.extend_from_slice(line);
+        }
+    }
+
+    kept
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Timestamp given to every packaged file: `SOURCE_DATE_EPOCH` when set, otherwise the
+/// time of the last commit, falling back to the Unix epoch outside of a git repository
+fn source_date_epoch(src_path: &Path) -> u64 {
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        match epoch.trim().parse() {
+            Ok(epoch) => return epoch,
+            Err(_) => eprintln!("Ignoring invalid SOURCE_DATE_EPOCH: {epoch}"),
+        }
+    }
+
+    git2::Repository::discover(src_path)
+        .and_then(|repo| {
+            let commit = repo.head()?.peel_to_commit()?;
+            Ok(commit.time().seconds().max(0) as u64)
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_nolib_blocks() {
+        let contents = b"local a = 1\n--@no-lib-strip@\nlocal lib = LibStub(\"Lib\")\n--@end-no-lib-strip@\nlocal b = 2\n";
+        assert_eq!(
+            strip_nolib_blocks(contents),
+            b"local a = 1\nlocal b = 2\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn strips_nolib_blocks_from_non_utf8_files() {
+        // "Café" in Latin-1
+        let contents = b"L[\"Caf\xe9\"] = true\n<!--@no-lib-strip@-->\n<Include file=\"libs\\Lib.xml\"/>\n<!--@end-no-lib-strip@-->\n";
+        assert_eq!(
+            strip_nolib_blocks(contents),
+            b"L[\"Caf\xe9\"] = true\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn keeps_files_without_blocks() {
+        let contents = b"local a = 1\nlocal b = 2";
+        assert_eq!(strip_nolib_blocks(contents), contents.to_vec());
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::Result;
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::common::{contained_path, MoxenError};
+
+use super::manifest::{External, PackageManifest};
+
+/// File names the CurseForge packager looks for, in order
+static PKGMETA_FILES: [&str; 3] = [".pkgmeta", "pkgmeta.yaml", "pkgmeta.yml"];
+
+/// The parts of a CurseForge `.pkgmeta` which Moxen understands
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PkgMeta {
+    pub package_as: Option<String>,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default)]
+    pub externals: BTreeMap<String, External>,
+    #[serde(default)]
+    pub move_folders: BTreeMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_yes_no")]
+    pub enable_nolib_creation: bool,
+}
+
+impl PkgMeta {
+    /// Reads the `.pkgmeta` of the project in `dir`, if it has one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let Some(path) = PKGMETA_FILES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read_to_string(&path)?;
+        let pkgmeta: Self = serde_yaml::from_str(&contents).map_err(|e| {
+            MoxenError::ConfigError(format!("unable to parse {} - {e}", path.display()))
+        })?;
+        pkgmeta.validate()?;
+
+        Ok(Some(pkgmeta))
+    }
+
+    /// Checks the settings can be packaged.
+    ///
+    /// `.pkgmeta` files come from third parties, so every path in one has to stay
+    /// inside the package and every folder name has to be a single folder. Externals
+    /// are fetched with git, so svn and other hosts are refused up front rather than
+    /// when packaging.
+    pub fn validate(&self) -> Result<(), MoxenError> {
+        if let Some(package_as) = &self.package_as {
+            check_folder_name("package-as", package_as)?;
+        }
+        for (path, external) in self.externals.iter() {
+            check_path("externals", path)?;
+            check_external(path, external)?;
+        }
+        for (path, addon) in self.move_folders.iter() {
+            check_path("move-folders", path)?;
+            check_folder_name("move-folders", addon)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the packaging settings into `manifest`, once they pass [`Self::validate`]
+    pub fn apply(self, manifest: &mut PackageManifest) -> Result<()> {
+        self.validate()?;
+
+        let mox = &mut manifest.mox;
+        if !self.ignore.is_empty() {
+            mox.ignore.get_or_insert_with(Vec::new).extend(self.ignore);
+        }
+        if !self.externals.is_empty() {
+            mox.externals = Some(self.externals);
+        }
+        if !self.move_folders.is_empty() {
+            // `.pkgmeta` paths start with the addon folder itself, Moxen.toml ones don't
+            let folders = self
+                .move_folders
+                .into_iter()
+                .map(|(path, addon)| match path.split_once('/') {
+                    Some((_, inner)) => (inner.to_string(), addon),
+                    None => (path, addon),
+                })
+                .collect();
+            mox.move_folders = Some(folders);
+        }
+        mox.package_as = self.package_as;
+        mox.nolib = self.enable_nolib_creation;
+
+        Ok(())
+    }
+}
+
+fn check_path(setting: &str, path: &str) -> Result<(), MoxenError> {
+    match contained_path(path) {
+        Some(_) => Ok(()),
+        None => {
+            eprintln!("`{path}` in {setting} must be a relative path inside the package");
+            Err(MoxenError::ConfigError(format!(
+                "invalid .pkgmeta {setting} entry `{path}`"
+            )))
+        }
+    }
+}
+
+fn check_external(path: &str, external: &External) -> Result<(), MoxenError> {
+    match external.kind() {
+        "git" => Ok(()),
+        kind => {
+            eprintln!(
+                "External `{path}` is hosted on {kind} ({}), only git externals can be fetched - \
+                 point it at a git mirror or vendor the library into the project",
+                external.url()
+            );
+            Err(MoxenError::ConfigError(format!(
+                "unsupported .pkgmeta externals entry `{path}`"
+            )))
+        }
+    }
+}
+
+fn check_folder_name(setting: &str, name: &str) -> Result<(), MoxenError> {
+    match contained_path(name) {
+        Some(path) if path.components().count() == 1 => Ok(()),
+        _ => {
+            eprintln!("`{name}` in {setting} must be the name of an addon folder");
+            Err(MoxenError::ConfigError(format!(
+                "invalid .pkgmeta {setting} entry `{name}`"
+            )))
+        }
+    }
+}
+
+/// `.pkgmeta` files are written for YAML 1.1, where `yes` and `no` are booleans
+fn deserialize_yes_no<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum YesNo {
+        Bool(bool),
+        Text(String),
+    }
+
+    match YesNo::deserialize(deserializer)? {
+        YesNo::Bool(value) => Ok(value),
+        YesNo::Text(text) => match text.to_lowercase().as_str() {
+            "yes" | "y" | "true" | "on" => Ok(true),
+            "no" | "n" | "false" | "off" => Ok(false),
+            _ => Err(serde::de::Error::custom(format!(
+                "expected yes or no, found `{text}`"
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> PkgMeta {
+        serde_yaml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn parses_yes_no_booleans() {
+        assert!(parse("enable-nolib-creation: yes").enable_nolib_creation);
+        assert!(parse("enable-nolib-creation: true").enable_nolib_creation);
+        assert!(!parse("enable-nolib-creation: no").enable_nolib_creation);
+        assert!(!parse("package-as: MyAddon").enable_nolib_creation);
+        assert!(serde_yaml::from_str::<PkgMeta>("enable-nolib-creation: maybe").is_err());
+    }
+
+    #[test]
+    fn parses_externals() {
+        let pkgmeta = parse(
+            "externals:\n  Libs/LibStub: https://repos.curseforge.com/wow/libstub/trunk\n  Libs/AceDB-3.0:\n    url: https://github.com/WoWUIDev/Ace3.git\n    tag: latest\n",
+        );
+
+        let stub = &pkgmeta.externals["Libs/LibStub"];
+        assert_eq!(stub.kind(), "svn");
+        let ace = &pkgmeta.externals["Libs/AceDB-3.0"];
+        assert_eq!(ace.url(), "https://github.com/WoWUIDev/Ace3.git");
+        assert_eq!(ace.kind(), "git");
+    }
+
+    #[test]
+    fn apply_strips_the_addon_folder_from_moved_folders() {
+        let mut manifest = PackageManifest::fresh("MyAddon");
+        parse("package-as: MyAddon\nmove-folders:\n  MyAddon/Modules/Config: MyAddon_Config\n")
+            .apply(&mut manifest)
+            .unwrap();
+
+        let moved = manifest.mox.move_folders.unwrap();
+        assert_eq!(moved["Modules/Config"], "MyAddon_Config");
+        assert_eq!(manifest.mox.package_as.as_deref(), Some("MyAddon"));
+    }
+
+    #[test]
+    fn apply_rejects_paths_leaving_the_package() {
+        for contents in [
+            "externals:\n  ../outside: https://example.com/lib.git\n",
+            "externals:\n  /tmp/outside: https://example.com/lib.git\n",
+            "move-folders:\n  MyAddon/../../outside: Other\n",
+            "move-folders:\n  MyAddon/Config: ../Other\n",
+            "package-as: ../MyAddon\n",
+        ] {
+            let mut manifest = PackageManifest::fresh("MyAddon");
+            assert!(parse(contents).apply(&mut manifest).is_err(), "{contents}");
+        }
+    }
+
+    #[test]
+    fn apply_rejects_externals_moxen_cannot_fetch() {
+        for contents in [
+            "externals:\n  Libs/LibStub: https://repos.curseforge.com/wow/libstub/trunk\n",
+            "externals:\n  Libs/LibStub: svn://svn.wowace.com/wow/libstub/mainline/trunk\n",
+            "externals:\n  Libs/Lib:\n    url: https://example.com/lib\n    type: hg\n",
+        ] {
+            let mut manifest = PackageManifest::fresh("MyAddon");
+            assert!(parse(contents).apply(&mut manifest).is_err(), "{contents}");
+            assert!(manifest.mox.externals.is_none());
+        }
+
+        let mut manifest = PackageManifest::fresh("MyAddon");
+        parse("externals:\n  Libs/AceDB-3.0: https://github.com/WoWUIDev/Ace3.git\n")
+            .apply(&mut manifest)
+            .unwrap();
+        assert!(manifest
+            .mox
+            .externals
+            .unwrap()
+            .contains_key("Libs/AceDB-3.0"));
+    }
+
+    #[test]
+    fn load_rejects_svn_externals() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".pkgmeta"),
+            "externals:\n  Libs/LibStub: https://repos.curseforge.com/wow/libstub/trunk\n",
+        )
+        .unwrap();
+
+        assert!(PkgMeta::load(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::common::MoxenError;
+
+#[derive(Debug)]
+pub enum PackageStatus {
+    Installed(Option<String>),
+    Updated((Option<String>, Option<String>)),
+    AlreadyPresent(Option<String>),
+    NotFound,
+    ChecksumMismatch,
+    NetworkError(String),
+    Failed(String),
+}
+
+/// Outcome of every package touched by an install or update
+#[derive(Debug, Default)]
+pub struct InstallReport {
+    packages: BTreeMap<String, PackageStatus>,
+}
+
+impl PackageStatus {
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        if let Some(err) = err.downcast_ref::<MoxenError>() {
+            return match err {
+                MoxenError::ProjectNotFound(_) => Self::NotFound,
+                MoxenError::ChecksumFailure(_) => Self::ChecksumMismatch,
+                err => Self::Failed(err.to_string().trim_end().to_owned()),
+            };
+        }
+
+        if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+            return Self::NetworkError(err.to_string());
+        }
+
+        Self::Failed(err.to_string())
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            self,
+            Self::Installed(_) | Self::Updated(_) | Self::AlreadyPresent(_)
+        )
+    }
+}
+
+impl InstallReport {
+    pub fn record(&mut self, name: &str, status: PackageStatus) {
+        self.packages.insert(name.to_owned(), status);
+    }
+
+    pub fn record_error(&mut self, name: &str, err: &anyhow::Error) {
+        self.record(name, PackageStatus::from_error(err));
+    }
+
+    pub fn succeeded(&self, name: &str) -> bool {
+        self.packages
+            .get(name)
+            .is_some_and(|status| !status.is_failure())
+    }
+
+    pub fn failures(&self) -> usize {
+        self.packages
+            .values()
+            .filter(|status| status.is_failure())
+            .count()
+    }
+}
+
+impl fmt::Display for PackageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Installed(version) => write!(f, "installed {}", display_version(version)),
+            Self::Updated((old, new)) => write!(
+                f,
+                "updated {} -> {}",
+                display_version(old),
+                display_version(new)
+            ),
+            Self::AlreadyPresent(version) => {
+                write!(f, "already present {}", display_version(version))
+            }
+            Self::NotFound => write!(f, "not found in registry"),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Self::NetworkError(err) => write!(f, "network error: {err}"),
+            Self::Failed(err) => write!(f, "failed: {err}"),
+        }
+    }
+}
+
+impl fmt::Display for InstallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .packages
+            .keys()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0);
+
+        writeln!(f, "--- Dependency Report ---")?;
+        for (name, status) in self.packages.iter() {
+            writeln!(f, "{name:<width$}  {status}")?;
+        }
+        writeln!(f, "------")?;
+
+        Ok(())
+    }
+}
+
+fn display_version(version: &Option<String>) -> String {
+    match version {
+        Some(version) => format!("v{version}"),
+        None => "(unversioned)".to_string(),
+    }
+}
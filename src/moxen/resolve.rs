@@ -0,0 +1,512 @@
+use anyhow::Result;
+use semver::{Version, VersionReq};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use crate::common::MoxenError;
+
+use super::{
+    api::{self, transfer::DownloadedPackage, PublisherKey, Registry},
+    download::{
+        download_dependency, download_version, installed_manifest, libs_dir, lock_satisfies,
+        resolve_version, version_satisfies,
+    },
+    lockfile::Lockfile,
+    manifest::{validate_dependency_name, NormalizedManifest},
+};
+
+pub struct Resolution {
+    pub packages: BTreeMap<String, ResolvedPackage>,
+    /// Packages which could not be fetched, their own dependencies are left unresolved
+    pub failures: BTreeMap<String, anyhow::Error>,
+}
+
+pub struct ResolvedPackage {
+    pub version: Option<String>,
+    pub dependencies: BTreeMap<String, String>,
+    /// Chain of packages which first pulled this one in, starting at the project
+    pub path: Vec<String>,
+    pub req: VersionReq,
+    pub registry: Registry,
+    pub source: PackageSource,
+}
+
+pub enum PackageSource {
+    /// Already vendored in `libs/`, with the registry manifest of the installed version
+    /// when Moxen.lock doesn't pin it yet
+    Installed(Option<Box<NormalizedManifest>>),
+    Registry {
+        manifest: Box<NormalizedManifest>,
+        package: DownloadedPackage,
+        publisher: Option<PublisherKey>,
+    },
+}
+
+/// Which vendored packages should be checked against the registry for newer versions
+pub enum Updates {
+    None,
+    All,
+    Only(HashSet<String>),
+}
+
+pub struct Resolver<'a> {
+    src_dir: &'a Path,
+    root: String,
+    lockfile: &'a Lockfile,
+    locked: bool,
+    updates: Updates,
+    require_signatures: bool,
+}
+
+impl ResolvedPackage {
+    fn satisfies(&self, req: &VersionReq) -> bool {
+        match (&self.source, &self.version) {
+            // Nothing to compare against for vendored packages without a manifest
+            (PackageSource::Installed(_), None) => true,
+            (_, version) => version_satisfies(version.as_deref(), req),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.version {
+            Some(version) => format!("v{version}"),
+            None => "an unversioned release".to_string(),
+        }
+    }
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(src_dir: &'a Path, root: String, lockfile: &'a Lockfile, locked: bool) -> Self {
+        Self {
+            src_dir,
+            root,
+            lockfile,
+            locked,
+            updates: Updates::None,
+            require_signatures: false,
+        }
+    }
+
+    pub fn updating(mut self, updates: Updates) -> Self {
+        self.updates = updates;
+        self
+    }
+
+    /// Refuses packages from the registry that don't carry a valid signature
+    pub fn requiring_signatures(mut self, require_signatures: bool) -> Self {
+        self.require_signatures = require_signatures;
+        self
+    }
+
+    /// Walks the dependency graph from `roots`, fetching anything not already vendored.
+    ///
+    /// Every package is resolved to a single version since they all share `libs/`,
+    /// so two incompatible requirements on the same package are a conflict. Cycles
+    /// and conflicts fail the whole resolution, anything else is recorded per package.
+    pub async fn resolve(&self, roots: Vec<(String, VersionReq, Registry)>) -> Result<Resolution> {
+        let mut packages: BTreeMap<String, ResolvedPackage> = BTreeMap::new();
+        let mut failures = BTreeMap::new();
+        let mut pending: Vec<(String, VersionReq, Registry, Vec<String>)> = vec![];
+        for (name, req, registry) in roots.into_iter().rev() {
+            validate_dependency_name(&name)?;
+            pending.push((name, req, registry, vec![self.root.clone()]));
+        }
+
+        while let Some((name, req, registry, path)) = pending.pop() {
+            if let Some(start) = path.iter().position(|p| *p == name) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(name);
+                anyhow::bail!(MoxenError::DependencyCycle(cycle.join(" -> ")));
+            }
+
+            if failures.contains_key(&name) {
+                continue;
+            }
+
+            if let Some(existing) = packages.get(&name) {
+                if !existing.satisfies(&req) {
+                    anyhow::bail!(MoxenError::DependencyConflict(format!(
+                        "{} requires {name} {req}, but {} requires {name} {} which resolved to {}",
+                        path.join(" -> "),
+                        existing.path.join(" -> "),
+                        existing.req,
+                        existing.describe(),
+                    )));
+                }
+                continue;
+            }
+
+            let pkg = match self.resolve_package(&name, req, registry, path).await {
+                Ok(pkg) => pkg,
+                Err(err) if is_fatal(&err) => return Err(err),
+                Err(err) => {
+                    failures.insert(name, err);
+                    continue;
+                }
+            };
+            let mut child_path = pkg.path.clone();
+            child_path.push(name.clone());
+            for (dep, dep_req) in pkg.dependencies.iter().rev() {
+                // Registry manifests name folders under libs/, so they mustn't leave it
+                validate_dependency_name(dep).map_err(|e| {
+                    MoxenError::GeneralError(format!("{name} has an invalid dependency - {e}"))
+                })?;
+                let dep_req = VersionReq::parse(dep_req).map_err(|e| {
+                    MoxenError::GeneralError(format!(
+                        "{name} has an invalid requirement on {dep} `{dep_req}` - {e}"
+                    ))
+                })?;
+                // Dependencies are looked up in the same registry as their parent
+                pending.push((
+                    dep.clone(),
+                    dep_req,
+                    pkg.registry.clone(),
+                    child_path.clone(),
+                ));
+            }
+            packages.insert(name, pkg);
+        }
+
+        Ok(Resolution { packages, failures })
+    }
+
+    async fn resolve_package(
+        &self,
+        name: &str,
+        req: VersionReq,
+        registry: Registry,
+        path: Vec<String>,
+    ) -> Result<ResolvedPackage> {
+        if libs_dir(self.src_dir, name).exists() {
+            let pkg = self.installed_package(name, req, registry, path);
+            if !self.updates.includes(name) {
+                if !pkg.satisfies(&pkg.req) {
+                    anyhow::bail!(MoxenError::DependencyConflict(format!(
+                        "{} requires {name} {}, but {} is already installed",
+                        pkg.path.join(" -> "),
+                        pkg.req,
+                        pkg.describe(),
+                    )));
+                }
+                return self.pin_installed(name, pkg).await;
+            }
+
+            let latest = resolve_version(&pkg.registry, name, &pkg.req).await?;
+            if same_version(latest.as_deref(), pkg.version.as_deref()) {
+                return self.pin_installed(name, pkg).await;
+            }
+            if latest.is_none() && pkg.version.is_none() && self.same_release(name, &pkg).await? {
+                return Ok(pkg);
+            }
+
+            let (manifest, package, publisher) = download_version(
+                &pkg.registry,
+                name,
+                latest.as_deref(),
+                self.require_signatures,
+            )
+            .await?;
+            return Ok(ResolvedPackage {
+                version: manifest.version.clone(),
+                dependencies: manifest.dependencies.clone(),
+                path: pkg.path,
+                req: pkg.req,
+                registry: pkg.registry,
+                source: PackageSource::Registry {
+                    manifest: Box::new(manifest),
+                    package,
+                    publisher,
+                },
+            });
+        }
+
+        let locked = self.lockfile.get(name);
+        if self.locked && !locked.is_some_and(|pkg| lock_satisfies(pkg, &req)) {
+            eprintln!("{name} ({req}) is not pinned in Moxen.lock");
+            anyhow::bail!(MoxenError::LockfileOutdated(name.to_string()));
+        }
+
+        let (manifest, package, publisher) =
+            download_dependency(&registry, name, &req, locked, self.require_signatures).await?;
+        Ok(ResolvedPackage {
+            version: manifest.version.clone(),
+            dependencies: manifest.dependencies.clone(),
+            path,
+            req,
+            registry,
+            source: PackageSource::Registry {
+                manifest: Box::new(manifest),
+                package,
+                publisher,
+            },
+        })
+    }
+
+    /// Whether an unversioned package is still the release pinned in Moxen.lock, which
+    /// the checksum is the only way to tell
+    async fn same_release(&self, name: &str, pkg: &ResolvedPackage) -> Result<bool> {
+        let Some(locked) = self.lockfile.get(name) else {
+            return Ok(false);
+        };
+
+        let manifest = api::fetch_manifest(&pkg.registry, name, None).await?;
+        let manifest = toml::from_str::<NormalizedManifest>(&manifest)?;
+        Ok(manifest.version.is_none() && manifest.has_checksum(&locked.cksum))
+    }
+
+    /// Looks up the registry manifest of an installed version which Moxen.lock doesn't
+    /// pin yet, so that it can be pinned like a fresh install
+    async fn pin_installed(&self, name: &str, mut pkg: ResolvedPackage) -> Result<ResolvedPackage> {
+        // Vendored packages without a manifest have no version to pin
+        let Some(version) = pkg.version.as_deref() else {
+            return Ok(pkg);
+        };
+        let pinned = self
+            .lockfile
+            .get(name)
+            .is_some_and(|locked| locked.version.as_deref() == Some(version));
+        if pinned {
+            return Ok(pkg);
+        }
+
+        if self.locked {
+            eprintln!("{name} v{version} is installed but not pinned in Moxen.lock");
+            anyhow::bail!(MoxenError::LockfileOutdated(name.to_string()));
+        }
+
+        let manifest = match api::fetch_manifest(&pkg.registry, name, Some(version)).await {
+            Ok(manifest) => toml::from_str::<NormalizedManifest>(&manifest)?,
+            Err(err) => {
+                eprintln!("Warning: unable to pin {name} v{version} in Moxen.lock - {err}");
+                return Ok(pkg);
+            }
+        };
+        pkg.source = PackageSource::Installed(Some(Box::new(manifest)));
+
+        Ok(pkg)
+    }
+
+    fn installed_package(
+        &self,
+        name: &str,
+        req: VersionReq,
+        registry: Registry,
+        path: Vec<String>,
+    ) -> ResolvedPackage {
+        let (version, dependencies) = match installed_manifest(self.src_dir, name) {
+            Some(manifest) => {
+                let dependencies = manifest
+                    .mox
+                    .dependencies
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(dep, spec)| {
+                        let version = spec.version().to_owned();
+                        (dep, version)
+                    })
+                    .collect();
+                (manifest.mox.version, dependencies)
+            }
+            None => (None, BTreeMap::new()),
+        };
+
+        ResolvedPackage {
+            version,
+            dependencies,
+            path,
+            req,
+            registry,
+            source: PackageSource::Installed(None),
+        }
+    }
+}
+
+impl Updates {
+    fn includes(&self, name: &str) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Only(names) => names.contains(name),
+        }
+    }
+}
+
+fn is_fatal(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<MoxenError>(),
+        Some(
+            MoxenError::DependencyCycle(_)
+                | MoxenError::DependencyConflict(_)
+                | MoxenError::LockfileOutdated(_)
+        )
+    )
+}
+
+/// Whether two versions are the same release. Unversioned releases can't be told
+/// apart by version, see [`Resolver::same_release`].
+fn same_version(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => match (Version::parse(a), Version::parse(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moxen::{
+        config::NetworkConfig,
+        lockfile::LockedPackage,
+        manifest::{Dependency, PackageManifest},
+    };
+
+    fn registry(dir: &Path) -> Registry {
+        Registry {
+            name: "test".to_string(),
+            url: format!("file://{}", dir.join("registry").display()),
+            ca_bundle: None,
+            accept_invalid_certs: false,
+            network: NetworkConfig::default(),
+            publisher_keys: BTreeMap::new(),
+        }
+    }
+
+    /// Vendors `name` into `libs/` and pins it, so resolving never needs the registry
+    fn install(
+        dir: &Path,
+        lockfile: &mut Lockfile,
+        name: &str,
+        version: &str,
+        deps: &[(&str, &str)],
+    ) {
+        let mut manifest = PackageManifest::fresh(name);
+        manifest.mox.version = Some(version.to_string());
+        for (dep, req) in deps {
+            manifest.add_dependency(dep.to_string(), Dependency::Version(req.to_string()));
+        }
+        let pkg_dir = libs_dir(dir, name);
+        std::fs::create_dir_all(&pkg_dir).unwrap();
+        manifest.write(&pkg_dir).unwrap();
+        lockfile.packages.push(LockedPackage {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            cksum: "sha256:aaaa".to_string(),
+        });
+    }
+
+    async fn resolve(
+        dir: &Path,
+        lockfile: &Lockfile,
+        roots: &[(&str, &str)],
+    ) -> Result<Resolution> {
+        let roots = roots
+            .iter()
+            .map(|(name, req)| {
+                (
+                    name.to_string(),
+                    VersionReq::parse(req).unwrap(),
+                    registry(dir),
+                )
+            })
+            .collect();
+        Resolver::new(dir, "app".to_string(), lockfile, false)
+            .resolve(roots)
+            .await
+    }
+
+    #[tokio::test]
+    async fn resolves_transitive_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lockfile = Lockfile::default();
+        install(dir.path(), &mut lockfile, "a", "1.2.0", &[("b", "^2")]);
+        install(dir.path(), &mut lockfile, "b", "2.0.1", &[]);
+
+        let resolution = resolve(dir.path(), &lockfile, &[("a", "^1")])
+            .await
+            .unwrap();
+        assert!(resolution.failures.is_empty());
+        assert_eq!(resolution.packages["a"].path, vec!["app"]);
+        assert_eq!(resolution.packages["b"].path, vec!["app", "a"]);
+        assert_eq!(resolution.packages["b"].version.as_deref(), Some("2.0.1"));
+    }
+
+    #[tokio::test]
+    async fn detects_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lockfile = Lockfile::default();
+        install(dir.path(), &mut lockfile, "a", "1.0.0", &[("b", "*")]);
+        install(dir.path(), &mut lockfile, "b", "1.0.0", &[("a", "*")]);
+
+        let err = resolve(dir.path(), &lockfile, &[("a", "*")])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<MoxenError>(),
+            Some(MoxenError::DependencyCycle(cycle)) if cycle == "a -> b -> a"
+        ));
+    }
+
+    #[tokio::test]
+    async fn detects_conflicting_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lockfile = Lockfile::default();
+        install(dir.path(), &mut lockfile, "a", "1.0.0", &[("c", "^1")]);
+        install(dir.path(), &mut lockfile, "b", "1.0.0", &[("c", "^2")]);
+        install(dir.path(), &mut lockfile, "c", "1.4.0", &[]);
+
+        let err = resolve(dir.path(), &lockfile, &[("a", "*"), ("b", "*")])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<MoxenError>(),
+            Some(MoxenError::DependencyConflict(reason))
+                if reason.starts_with("app -> b requires c ^2")
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_installed_versions_outside_the_requirement() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lockfile = Lockfile::default();
+        install(dir.path(), &mut lockfile, "a", "1.0.0", &[]);
+
+        let err = resolve(dir.path(), &lockfile, &[("a", "^2")])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<MoxenError>(),
+            Some(MoxenError::DependencyConflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_dependency_names_leaving_libs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lockfile = Lockfile::default();
+        install(dir.path(), &mut lockfile, "a", "1.0.0", &[("../../x", "*")]);
+
+        assert!(resolve(dir.path(), &lockfile, &[("a", "*")]).await.is_err());
+        assert!(resolve(dir.path(), &lockfile, &[("..", "*")])
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn compares_versions() {
+        assert!(same_version(Some("1.0.0"), Some("1.0.0")));
+        assert!(same_version(Some("1.0.0+build"), Some("1.0.0+build")));
+        assert!(same_version(Some("r42"), Some("r42")));
+        assert!(!same_version(Some("1.0.0"), Some("1.0.1")));
+        assert!(!same_version(Some("1.0.0"), None));
+        // Unversioned releases are told apart by checksum instead
+        assert!(!same_version(None, None));
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write;
+
+use super::manifest::NormalizedManifest;
+
+static MAX_DESCRIPTION_WIDTH: usize = 50;
+
+/// Lays search results out as an aligned table
+pub fn render_table(results: &[NormalizedManifest]) -> String {
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|manifest| {
+            let categories: Vec<String> = manifest
+                .categories
+                .iter()
+                .map(|category| category.to_string())
+                .collect();
+
+            [
+                manifest.name.clone(),
+                manifest.version.clone().unwrap_or_else(|| "-".to_string()),
+                truncate(&manifest.description, MAX_DESCRIPTION_WIDTH),
+                categories.join(", "),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "NAME".to_string(),
+        "VERSION".to_string(),
+        "DESCRIPTION".to_string(),
+        "CATEGORIES".to_string(),
+    ];
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in rows.iter() {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+
+    out
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(width - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
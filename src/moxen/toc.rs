@@ -0,0 +1,516 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::{common::MoxenError, DocumentationType};
+
+use super::manifest::{Flavor, PackageManifest, WowVersion};
+
+static GENERATED_HEADER: &str =
+    "# Generated by Moxen from Moxen.toml - only edit between the custom markers below";
+static CUSTOM_BEGIN: &str = "# Moxen: begin custom";
+static CUSTOM_END: &str = "# Moxen: end custom";
+
+/// Placeholder `## Interface:` for projects whose `wow_version` is not filled in yet
+pub static INTERFACE_PLACEHOLDER: &str = "<Current World of Warcraft Version Here (e.g. 110001)>";
+
+/// Directories which never contribute files to the generated TOC
+static SKIPPED_DIRS: [&str; 2] = ["libs", "docs"];
+
+/// A parsed TOC file
+#[derive(Debug, Default)]
+pub struct Toc {
+    /// `## Key: Value` directives in file order, `## X-` fields included
+    pub directives: Vec<(String, String)>,
+    pub files: Vec<TocEntry>,
+}
+
+/// A file loaded by a TOC
+#[derive(Debug)]
+pub struct TocEntry {
+    /// Path as written in the TOC, relative to the addon folder
+    pub path: String,
+    /// Trailing load conditions such as `AllowLoadGameType classic`
+    pub conditions: Vec<String>,
+    pub line: usize,
+}
+
+impl Toc {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut toc = Self::default();
+        let contents = contents.trim_start_matches('\u{feff}');
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if let Some(directive) = line.strip_prefix("##") {
+                if let Some((key, value)) = directive.split_once(':') {
+                    toc.directives
+                        .push((key.trim().to_string(), value.trim().to_string()));
+                }
+            } else if !line.is_empty() && !line.starts_with('#') {
+                toc.files.push(TocEntry::parse(line, idx + 1));
+            }
+        }
+
+        toc
+    }
+
+    /// Value of the `## key:` directive, matched case-insensitively like the game does
+    pub fn directive(&self, key: &str) -> Option<&str> {
+        self.directives
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl TocEntry {
+    fn parse(line: &str, number: usize) -> Self {
+        let mut path = line;
+        let mut conditions = vec![];
+        // `[...]` after whitespace is a condition, inside the path it is a variable like `[Family]`
+        while path.ends_with(']') {
+            match path.rfind('[') {
+                Some(idx) if path[..idx].ends_with(char::is_whitespace) => {
+                    conditions.insert(0, path[idx + 1..path.len() - 1].trim().to_string());
+                    path = path[..idx].trim_end();
+                }
+                _ => break,
+            }
+        }
+
+        Self {
+            path: path.to_string(),
+            conditions,
+            line: number,
+        }
+    }
+
+    /// Path with forward slashes, for looking the file up on disk
+    pub fn relative_path(&self) -> String {
+        normalise_path(&self.path)
+    }
+
+    /// Whether the path uses a variable such as `[Family]`, which only the game can resolve
+    pub fn has_variables(&self) -> bool {
+        self.path.contains('[')
+    }
+}
+
+/// File name of the TOC without its extension, which has to match the addon folder
+pub fn toc_stem(manifest: &PackageManifest) -> String {
+    manifest.addon_folder().replace(' ', "")
+}
+
+/// Location of the TOC for the project in `dir`
+pub fn toc_path(dir: &Path, manifest: &PackageManifest) -> PathBuf {
+    dir.join(format!("{}.toc", toc_stem(manifest)))
+}
+
+/// Location of the TOC the `flavor` client loads instead of the unsuffixed one
+pub fn flavor_toc_path(
+    dir: &Path,
+    manifest: &PackageManifest,
+    flavor: &DocumentationType,
+) -> PathBuf {
+    dir.join(format!(
+        "{}_{}.toc",
+        toc_stem(manifest),
+        flavor.toc_suffix()
+    ))
+}
+
+/// Whether the TOC at `path` was written by Moxen (and so can be regenerated)
+pub fn is_generated(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.starts_with(GENERATED_HEADER))
+        .unwrap_or(false)
+}
+
+/// The `## Interface:` value for `versions`, e.g. `110002, 40400`
+pub fn interface_line(versions: &[WowVersion]) -> String {
+    WowVersion::join(versions, |version| version.interface().to_string())
+}
+
+/// (Re)writes the TOC of the project in `dir`, plus one suffixed TOC per flavor in
+/// Moxen.toml, keeping the custom section of each.
+///
+/// A TOC which was not generated by Moxen is only replaced with `force`.
+pub fn write_toc(
+    dir: &Path,
+    manifest: &PackageManifest,
+    ignore: &[PathBuf],
+    force: bool,
+) -> Result<Vec<PathBuf>> {
+    let interface = interface_line(&manifest.wow_versions()?);
+    let mut tocs = vec![(toc_path(dir, manifest), interface, None)];
+    for (kind, flavor) in manifest.mox.flavors.iter().flatten() {
+        let path = flavor_toc_path(dir, manifest, kind);
+        if tocs.iter().any(|(other, _, _)| *other == path) {
+            anyhow::bail!(MoxenError::ConfigError(format!(
+                "more than one flavor generates {}",
+                path.display()
+            )));
+        }
+        let versions = WowVersion::parse_list(&flavor.interface).map_err(|e| {
+            MoxenError::ConfigError(format!("invalid interface for the {kind:?} flavor - {e}"))
+        })?;
+        tocs.push((path, interface_line(&versions), Some(flavor)));
+    }
+
+    // Render every TOC first, so a hand-written one stops the run before any is replaced
+    let mut rendered = vec![];
+    for (path, interface, flavor) in tocs {
+        let custom = existing_custom_section(&path, force)?;
+        let contents = generate_toc(dir, manifest, &interface, flavor, ignore, &custom)?;
+        rendered.push((path, contents));
+    }
+
+    let mut written = vec![];
+    for (path, contents) in rendered {
+        std::fs::write(&path, contents)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Custom section of the TOC at `path`, refusing to touch a hand-written one without `force`
+fn existing_custom_section(path: &Path, force: bool) -> Result<String> {
+    let custom = match std::fs::read_to_string(path) {
+        Ok(existing) if existing.starts_with(GENERATED_HEADER) || force => {
+            custom_section(&existing)
+        }
+        Ok(_) => {
+            eprintln!(
+                "{} was not generated by Moxen, move anything worth keeping between `{CUSTOM_BEGIN}` \
+                 and `{CUSTOM_END}` and re-run with --force",
+                path.display()
+            );
+            anyhow::bail!(MoxenError::GeneralError(
+                "refusing to overwrite a hand-written TOC".to_string()
+            ));
+        }
+        Err(_) => String::new(),
+    };
+
+    Ok(custom)
+}
+
+/// Renders the TOC for the project in `dir`.
+///
+/// Files come from `files` in Moxen.toml, or every Lua and XML file in the project
+/// otherwise, after the files of any dependency vendored in `libs/`. A `flavor` adds
+/// and removes files on top of those.
+pub fn generate_toc(
+    dir: &Path,
+    manifest: &PackageManifest,
+    interface: &str,
+    flavor: Option<&Flavor>,
+    ignore: &[PathBuf],
+    custom: &str,
+) -> Result<String> {
+    let mox = &manifest.mox;
+    let mut lines = vec![
+        GENERATED_HEADER.to_string(),
+        format!("## Interface: {interface}"),
+        format!("## Title: {}", mox.title.as_deref().unwrap_or(&mox.name)),
+        format!("## Version: {}", mox.version.as_deref().unwrap_or("0.1.0")),
+        format!("## Notes: {}", mox.description),
+    ];
+
+    if !mox.authors.is_empty() {
+        lines.push(format!("## Author: {}", mox.authors.join(", ")));
+    }
+
+    let deps: Vec<&str> = mox
+        .dependencies
+        .iter()
+        .flatten()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !deps.is_empty() {
+        lines.push(format!("## X-Embeds: {}", deps.join(", ")));
+    }
+    if let Some(vars) = mox.saved_variables.as_ref().filter(|v| !v.is_empty()) {
+        lines.push(format!("## SavedVariables: {}", vars.join(", ")));
+    }
+    if let Some(vars) = mox
+        .saved_variables_per_character
+        .as_ref()
+        .filter(|v| !v.is_empty())
+    {
+        lines.push(format!(
+            "## SavedVariablesPerCharacter: {}",
+            vars.join(", ")
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push(CUSTOM_BEGIN.to_string());
+    if !custom.is_empty() {
+        lines.push(custom.to_string());
+    }
+    lines.push(CUSTOM_END.to_string());
+    lines.push(String::new());
+
+    let mut files = vec![];
+    for dep in deps {
+        files.extend(dependency_files(dir, dep)?);
+    }
+
+    match &mox.files {
+        Some(listed) => files.extend(listed.iter().map(|file| normalise_path(file))),
+        None => files.extend(discover_files(dir, ignore)?),
+    }
+
+    // Moved folders are addons of their own with their own TOC
+    let moved = folder_prefixes(mox.move_folders.iter().flat_map(|m| m.keys()));
+    files.retain(|file| {
+        !moved
+            .iter()
+            .any(|prefix| file.to_lowercase().starts_with(prefix))
+    });
+
+    // Files added by any flavor only belong in that flavor's TOC
+    let flavored: Vec<String> = mox
+        .flavors
+        .iter()
+        .flatten()
+        .flat_map(|(_, flavor)| flavor.extra_files.iter().map(|file| normalise_path(file)))
+        .collect();
+    files.retain(|file| !flavored.contains(file));
+
+    if let Some(flavor) = flavor {
+        let excluded: Vec<String> = flavor
+            .exclude_files
+            .iter()
+            .map(|file| normalise_path(file))
+            .collect();
+        files.retain(|file| !excluded.contains(file));
+        files.extend(flavor.extra_files.iter().map(|file| normalise_path(file)));
+    }
+    lines.extend(files.iter().map(|file| file.replace('/', "\\")));
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Lowercased `path/` prefixes for matching the files below each of `paths`
+pub fn folder_prefixes<'a>(paths: impl Iterator<Item = &'a String>) -> Vec<String> {
+    paths
+        .map(|path| {
+            format!(
+                "{}/",
+                path.replace('\\', "/").trim_end_matches('/').to_lowercase()
+            )
+        })
+        .collect()
+}
+
+/// TOC entries are compared with forward slashes and written with backslashes
+fn normalise_path(file: &str) -> String {
+    file.replace('\\', "/")
+}
+
+/// Lines between the custom markers of an existing TOC
+fn custom_section(toc: &str) -> String {
+    toc.lines()
+        .skip_while(|line| line.trim() != CUSTOM_BEGIN)
+        .skip(1)
+        .take_while(|line| line.trim() != CUSTOM_END)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Files loaded by the TOC of a dependency vendored in `libs/<dep>`, relative to `dir`
+fn dependency_files(dir: &Path, dep: &str) -> Result<Vec<String>> {
+    let lib_dir = dir.join("libs").join(dep);
+    let pattern = lib_dir.join("*.toc");
+    let Some(toc) = glob::glob(&pattern.to_string_lossy())?.flatten().next() else {
+        eprintln!("Dependency `{dep}` is not installed, run `moxen add` to embed it in the TOC");
+        return Ok(vec![]);
+    };
+
+    let files = Toc::load(&toc)?
+        .files
+        .into_iter()
+        .map(|entry| {
+            let mut line = format!("libs/{dep}/{}", entry.relative_path());
+            for condition in entry.conditions {
+                line.push_str(&format!(" [{condition}]"));
+            }
+            line
+        })
+        .collect();
+
+    Ok(files)
+}
+
+/// Every Lua and XML file in the project, in a stable order
+pub fn discover_files(dir: &Path, ignore: &[PathBuf]) -> Result<Vec<String>> {
+    let mut files = vec![];
+    collect_sources(dir, dir, ignore, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+fn collect_sources(
+    root: &Path,
+    dir: &Path,
+    ignore: &[PathBuf],
+    files: &mut Vec<String>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || ignore.contains(&path) {
+            continue;
+        }
+
+        if path.is_dir() {
+            if dir == root && SKIPPED_DIRS.contains(&name.as_ref()) {
+                continue;
+            }
+            collect_sources(root, &path, ignore, files)?;
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("lua" | "xml")
+        ) {
+            let relative = path.strip_prefix(root)?;
+            files.push(normalise_path(&relative.to_string_lossy()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives_comments_and_files() {
+        let toc = Toc::parse(
+            "\u{feff}## Interface: 110002\n## Title : My Addon\n# a comment\n\nCore.lua\nUI\\Frames.xml\n",
+        );
+
+        assert_eq!(toc.directive("interface"), Some("110002"));
+        assert_eq!(toc.directive("Title"), Some("My Addon"));
+        let files: Vec<_> = toc.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, ["Core.lua", "UI\\Frames.xml"]);
+        assert_eq!(toc.files[0].line, 5);
+        assert_eq!(toc.files[1].relative_path(), "UI/Frames.xml");
+    }
+
+    #[test]
+    fn parses_load_conditions() {
+        let entry = TocEntry::parse("Classic.lua [AllowLoadGameType classic, bcc]", 3);
+        assert_eq!(entry.path, "Classic.lua");
+        assert_eq!(entry.conditions, ["AllowLoadGameType classic, bcc"]);
+        assert!(!entry.has_variables());
+
+        let entry = TocEntry::parse("Both.lua [AllowLoadGameType mainline] [AllowLoad Glue]", 4);
+        assert_eq!(entry.path, "Both.lua");
+        assert_eq!(
+            entry.conditions,
+            ["AllowLoadGameType mainline", "AllowLoad Glue"]
+        );
+    }
+
+    #[test]
+    fn keeps_variables_in_paths() {
+        let entry = TocEntry::parse("Flavor\\[Family].lua", 1);
+        assert_eq!(entry.path, "Flavor\\[Family].lua");
+        assert!(entry.conditions.is_empty());
+        assert!(entry.has_variables());
+    }
+
+    #[test]
+    fn names_tocs_after_the_addon_folder() {
+        let mut manifest = PackageManifest::fresh("My Addon");
+        assert_eq!(toc_stem(&manifest), "MyAddon");
+
+        manifest.mox.package_as = Some("Other Addon".to_string());
+        assert_eq!(
+            toc_path(Path::new("project"), &manifest),
+            Path::new("project").join("OtherAddon.toc")
+        );
+    }
+
+    fn project(dir: &Path) -> PackageManifest {
+        std::fs::write(dir.join("Core.lua"), "").unwrap();
+        std::fs::write(dir.join("Vanilla.lua"), "").unwrap();
+        let mut manifest = PackageManifest::fresh("Addon");
+        manifest.mox.wow_version = "11.0.2".to_string();
+        manifest
+    }
+
+    #[test]
+    fn keeps_the_custom_section_when_regenerating() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = project(dir.path());
+        let path = toc_path(dir.path(), &manifest);
+
+        write_toc(dir.path(), &manifest, &[], false).unwrap();
+        let edited = std::fs::read_to_string(&path).unwrap().replace(
+            CUSTOM_END,
+            &format!("## X-Website: example.com\nExtra.lua\n{CUSTOM_END}"),
+        );
+        std::fs::write(&path, edited).unwrap();
+
+        write_toc(dir.path(), &manifest, &[], false).unwrap();
+        let toc = std::fs::read_to_string(&path).unwrap();
+        assert!(toc.contains(&format!(
+            "{CUSTOM_BEGIN}\n## X-Website: example.com\nExtra.lua\n{CUSTOM_END}"
+        )));
+        assert_eq!(custom_section(&toc), "## X-Website: example.com\nExtra.lua");
+    }
+
+    #[test]
+    fn refuses_to_overwrite_hand_written_tocs() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = project(dir.path());
+        let path = toc_path(dir.path(), &manifest);
+        let hand_written = "## Interface: 110002\n## Title: Mine\nCore.lua\n";
+        std::fs::write(&path, hand_written).unwrap();
+
+        assert!(write_toc(dir.path(), &manifest, &[], false).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), hand_written);
+        assert!(!is_generated(&path));
+
+        write_toc(dir.path(), &manifest, &[], true).unwrap();
+        assert!(is_generated(&path));
+    }
+
+    #[test]
+    fn flavor_files_only_appear_in_their_toc() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = project(dir.path());
+        let flavor = Flavor {
+            interface: "1.15.4".to_string(),
+            extra_files: vec!["Vanilla.lua".to_string()],
+            exclude_files: vec![],
+        };
+        manifest.mox.flavors = Some([(DocumentationType::Vanilla, flavor)].into());
+
+        let written = write_toc(dir.path(), &manifest, &[], false).unwrap();
+        let vanilla_path = flavor_toc_path(dir.path(), &manifest, &DocumentationType::Vanilla);
+        assert_eq!(
+            written,
+            [toc_path(dir.path(), &manifest), vanilla_path.clone()]
+        );
+
+        let main = Toc::load(&toc_path(dir.path(), &manifest)).unwrap();
+        let files: Vec<_> = main.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, ["Core.lua"]);
+        assert_eq!(main.directive("Interface"), Some("110002"));
+
+        let vanilla = Toc::load(&vanilla_path).unwrap();
+        let files: Vec<_> = vanilla.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, ["Core.lua", "Vanilla.lua"]);
+        assert_eq!(vanilla.directive("Interface"), Some("11504"));
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    path::Path,
+};
+
+use crate::common::MoxenError;
+
+use super::{
+    download::{installed_manifest, installed_packages, libs_dir},
+    manifest::PackageManifest,
+};
+
+/// Dependency graph built from the project manifest and the manifests vendored in `libs/`
+pub struct DependencyGraph {
+    root: String,
+    nodes: BTreeMap<String, GraphNode>,
+}
+
+struct GraphNode {
+    version: Option<String>,
+    installed: bool,
+    dependencies: BTreeMap<String, String>,
+}
+
+impl DependencyGraph {
+    pub fn load(src_dir: &Path, manifest: &PackageManifest) -> Result<Self> {
+        let root = manifest.normalise_name(false);
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            root.clone(),
+            GraphNode {
+                version: manifest.mox.version.clone(),
+                installed: true,
+                dependencies: manifest_dependencies(manifest),
+            },
+        );
+
+        let mut pending: Vec<String> = nodes[&root].dependencies.keys().cloned().collect();
+        // Pick up anything vendored that nothing points at any more too
+        pending.extend(installed_packages(src_dir)?);
+
+        while let Some(name) = pending.pop() {
+            if nodes.contains_key(&name) {
+                continue;
+            }
+
+            let node = match installed_manifest(src_dir, &name) {
+                Some(manifest) => GraphNode {
+                    version: manifest.mox.version.clone(),
+                    installed: true,
+                    dependencies: manifest_dependencies(&manifest),
+                },
+                None => GraphNode {
+                    version: None,
+                    installed: libs_dir(src_dir, &name).exists(),
+                    dependencies: BTreeMap::new(),
+                },
+            };
+            pending.extend(node.dependencies.keys().cloned());
+            nodes.insert(name, node);
+        }
+
+        Ok(Self { root, nodes })
+    }
+
+    pub fn render_text(&self, invert: Option<&str>) -> Result<String> {
+        let start = self.start(invert)?;
+        let mut out = String::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![];
+        writeln!(out, "{}", self.label(start))?;
+        self.render_children(start, invert.is_some(), "", &mut seen, &mut stack, &mut out)?;
+
+        if invert.is_none() {
+            let reachable = self.reachable_from(start, false);
+            let orphans: Vec<&String> = self
+                .nodes
+                .keys()
+                .filter(|name| !reachable.contains(name))
+                .collect();
+            if !orphans.is_empty() {
+                writeln!(out, "\nInstalled but not required by anything:")?;
+                for name in orphans.into_iter() {
+                    writeln!(out, "- {}", self.label(name))?;
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn render_dot(&self, invert: Option<&str>) -> Result<String> {
+        let start = self.start(invert)?;
+        let reachable = self.reachable_from(start, invert.is_some());
+
+        let mut out = String::new();
+        writeln!(out, "digraph dependencies {{")?;
+        for name in reachable.iter() {
+            writeln!(out, "    \"{name}\" [label=\"{}\"];", self.label(name))?;
+        }
+        for name in reachable.iter() {
+            for (child, req) in self.edges(name, invert.is_some()) {
+                writeln!(out, "    \"{name}\" -> \"{child}\" [label=\"{req}\"];")?;
+            }
+        }
+        writeln!(out, "}}")?;
+
+        Ok(out)
+    }
+
+    fn start<'a>(&'a self, invert: Option<&'a str>) -> Result<&'a str> {
+        match invert {
+            Some(pkg) if !self.nodes.contains_key(pkg) => {
+                anyhow::bail!(MoxenError::GeneralError(format!(
+                    "{pkg} is not part of the dependency graph"
+                )))
+            }
+            Some(pkg) => Ok(pkg),
+            None => Ok(&self.root),
+        }
+    }
+
+    fn render_children(
+        &self,
+        name: &str,
+        inverted: bool,
+        prefix: &str,
+        seen: &mut HashSet<String>,
+        stack: &mut Vec<String>,
+        out: &mut String,
+    ) -> Result<()> {
+        stack.push(name.to_owned());
+        let edges = self.edges(name, inverted);
+        let count = edges.len();
+        for (idx, (child, _)) in edges.into_iter().enumerate() {
+            let last = idx + 1 == count;
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+
+            let label = self.label(&child);
+            if stack.contains(&child) {
+                writeln!(out, "{prefix}{branch}{label} (cycle)")?;
+                continue;
+            }
+
+            if !seen.insert(child.clone()) {
+                // Already shown elsewhere in the tree
+                writeln!(out, "{prefix}{branch}{label} (*)")?;
+                continue;
+            }
+
+            writeln!(out, "{prefix}{branch}{label}")?;
+            let prefix = format!("{prefix}{indent}");
+            self.render_children(&child, inverted, &prefix, seen, stack, out)?;
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    /// Outgoing edges of `name`, or the packages depending on it when `inverted`
+    fn edges(&self, name: &str, inverted: bool) -> Vec<(String, String)> {
+        if !inverted {
+            return match self.nodes.get(name) {
+                Some(node) => node
+                    .dependencies
+                    .iter()
+                    .map(|(dep, req)| (dep.clone(), req.clone()))
+                    .collect(),
+                None => vec![],
+            };
+        }
+
+        self.nodes
+            .iter()
+            .filter_map(|(parent, node)| {
+                node.dependencies
+                    .get(name)
+                    .map(|req| (parent.clone(), req.clone()))
+            })
+            .collect()
+    }
+
+    fn reachable_from(&self, start: &str, inverted: bool) -> Vec<String> {
+        let mut visited = vec![start.to_owned()];
+        let mut pending = vec![start.to_owned()];
+        while let Some(name) = pending.pop() {
+            for (child, _) in self.edges(&name, inverted) {
+                if !visited.contains(&child) {
+                    visited.push(child.clone());
+                    pending.push(child);
+                }
+            }
+        }
+
+        visited
+    }
+
+    fn label(&self, name: &str) -> String {
+        match self.nodes.get(name) {
+            Some(node) if !node.installed => format!("{name} (not installed)"),
+            Some(GraphNode {
+                version: Some(version),
+                ..
+            }) => format!("{name} v{version}"),
+            _ => name.to_owned(),
+        }
+    }
+}
+
+fn manifest_dependencies(manifest: &PackageManifest) -> BTreeMap<String, String> {
+    match &manifest.mox.dependencies {
+        Some(deps) => deps
+            .iter()
+            .map(|(name, dep)| (name.clone(), dep.version().to_owned()))
+            .collect(),
+        None => BTreeMap::new(),
+    }
+}